@@ -0,0 +1,726 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::chunk_data::{
+    join_frame_data, split_frame_data, AnimationControl, BlendOp, DisposeOp, FrameControl,
+    ImageHeader,
+};
+use crate::chunk_type::ChunkType;
+use crate::image::{filtered_image_len, Image, INDEXED, TRUECOLOR_ALPHA};
+use crate::png::Png;
+use crate::rewrite::inflate;
+use crate::Result;
+
+/// Denominator of frame delays given in milliseconds
+pub const MILLISECONDS: u16 = 1000;
+
+/// Chunks telling how samples map to colours, carried over from the frames by [`join_frames`]
+pub const COLOR_CHUNK_TYPES: &[&str] = &["cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "tRNS"];
+
+/// An animation frame and the chunks that carry its image data
+#[derive(Debug, Clone, Serialize)]
+pub struct Frame {
+    pub index: usize,
+    #[serde(flatten)]
+    pub control: FrameControl,
+    /// Delay in seconds
+    pub delay: f64,
+    /// Indices into the png's chunks of the IDAT or fdAT chunks holding the frame
+    pub data_chunks: Vec<usize>,
+    /// True if the frame is the default image, stored in IDAT
+    pub is_default_image: bool,
+}
+
+/// Animation view over a png carrying acTL, fcTL and fdAT chunks
+pub struct Apng<'a> {
+    png: &'a Png,
+    header: ImageHeader,
+    control: AnimationControl,
+    frames: Vec<Frame>,
+}
+
+impl<'a> Apng<'a> {
+    /// True if `png` declares an animation
+    pub fn is_animated(png: &Png) -> bool {
+        png.chunk_by_type("acTL").is_some()
+    }
+
+    /// Reads the animation chunks of `png`, checking their order, sequence numbers and frame
+    /// bounds
+    pub fn new(png: &'a Png) -> Result<Self> {
+        let header = ImageHeader::from_bytes(
+            png.chunk_by_type("IHDR")
+                .ok_or(anyhow!("png has no IHDR chunk"))?
+                .data(),
+        )?;
+
+        let mut control = None;
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut seen_image_data = false;
+        let mut next_sequence_number = 0;
+        let mut check_sequence = |sequence_number: u32, chunk_type: &str| {
+            if sequence_number != next_sequence_number {
+                bail!(
+                    "{} has sequence number {}, expected {}",
+                    chunk_type,
+                    sequence_number,
+                    next_sequence_number
+                )
+            }
+            next_sequence_number += 1;
+            Ok(())
+        };
+
+        for (index, chunk) in png.chunks().iter().enumerate() {
+            match &chunk.chunk_type().bytes() {
+                b"acTL" => {
+                    if control.is_some() {
+                        bail!("png has more than one acTL chunk")
+                    }
+                    if seen_image_data {
+                        bail!("acTL must come before the first IDAT chunk")
+                    }
+                    control = Some(AnimationControl::from_bytes(chunk.data())?);
+                }
+                b"fcTL" => {
+                    let frame = FrameControl::from_bytes(chunk.data())?;
+                    check_sequence(frame.sequence_number, "fcTL")?;
+                    check_bounds(&frame, &header, frames.len())?;
+                    if !seen_image_data
+                        && (frame.width, frame.height, frame.x_offset, frame.y_offset)
+                            != (header.width, header.height, 0, 0)
+                    {
+                        bail!("the fcTL of the default image must cover the whole image")
+                    }
+
+                    frames.push(Frame {
+                        index: frames.len(),
+                        control: frame,
+                        delay: frame.delay(),
+                        data_chunks: Vec::new(),
+                        is_default_image: !seen_image_data,
+                    });
+                }
+                b"IDAT" => {
+                    seen_image_data = true;
+                    if let Some(frame) = frames.last_mut().filter(|frame| frame.is_default_image) {
+                        frame.data_chunks.push(index);
+                    }
+                }
+                b"fdAT" => {
+                    let (sequence_number, _) = split_frame_data(chunk.data())?;
+                    check_sequence(sequence_number, "fdAT")?;
+                    match frames.last_mut() {
+                        Some(frame) if !frame.is_default_image => frame.data_chunks.push(index),
+                        _ => bail!("fdAT chunk {} does not follow an fcTL after IDAT", index),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let control = control.ok_or(anyhow!("png has no acTL chunk, it is not animated"))?;
+        if control.num_frames == 0 {
+            bail!("acTL declares no frames")
+        }
+        if frames.len() != control.num_frames as usize {
+            bail!(
+                "acTL declares {} frames but the png has {}",
+                control.num_frames,
+                frames.len()
+            )
+        }
+        if let Some(frame) = frames.iter().find(|frame| frame.data_chunks.is_empty()) {
+            bail!("frame {} has no image data", frame.index)
+        }
+
+        Ok(Self {
+            png,
+            header,
+            control,
+            frames,
+        })
+    }
+
+    pub fn png(&self) -> &'a Png {
+        self.png
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    pub fn control(&self) -> &AnimationControl {
+        &self.control
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// True if the default image is shown as the first frame rather than only by non-animating
+    /// decoders
+    pub fn default_image_is_frame(&self) -> bool {
+        self.frames[0].is_default_image
+    }
+
+    /// Joins the zlib stream of a frame from its IDAT chunks, or its fdAT chunks without their
+    /// sequence numbers
+    pub fn compressed_frame_data(&self, frame: &Frame) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for index in &frame.data_chunks {
+            let chunk = &self.png.chunks()[*index];
+            if frame.is_default_image {
+                data.extend_from_slice(chunk.data());
+            } else {
+                data.extend_from_slice(split_frame_data(chunk.data())?.1);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Decodes the pixels of a frame's own region as 8-bit RGBA
+    pub fn decode_frame(&self, frame: &Frame) -> Result<Image> {
+        let header = ImageHeader {
+            width: frame.control.width,
+            height: frame.control.height,
+            ..self.header
+        };
+
+        let filtered = inflate(
+            &self.compressed_frame_data(frame)?,
+            filtered_image_len(&header)?,
+        )?;
+
+        Image::from_filtered(&header, &filtered)?.to_rgba8(
+            self.png.chunk_by_type("PLTE").map(|chunk| chunk.data()),
+            self.png.chunk_by_type("tRNS").map(|chunk| chunk.data()),
+        )
+    }
+
+    /// Decodes the image stored in IDAT as 8-bit RGBA, whether or not it is a frame
+    fn decode_default_image(&self) -> Result<Image> {
+        Image::decode(self.png)?.to_rgba8(
+            self.png.chunk_by_type("PLTE").map(|chunk| chunk.data()),
+            self.png.chunk_by_type("tRNS").map(|chunk| chunk.data()),
+        )
+    }
+
+    /// Renders every frame onto the full canvas as an animating decoder shows it, applying the
+    /// blend op of each frame and the dispose op of the one before. `show` is called with the
+    /// index of each frame and the canvas as it appears during that frame, so only one canvas is
+    /// held in memory however many frames there are.
+    pub fn render<F>(&self, mut show: F) -> Result<()>
+    where
+        F: FnMut(usize, &Image) -> Result<()>,
+    {
+        // The default image covers the whole canvas, so decoding it first checks the IHDR
+        // dimensions against the image data before the canvas is allocated for them
+        let default_image = self.decode_default_image()?;
+        let width = self.header.width as usize;
+        let canvas_bytes = width
+            .checked_mul(self.header.height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or(anyhow!("the canvas is too large to render"))?;
+        let mut canvas = Image::new(
+            self.header.width,
+            self.header.height,
+            TRUECOLOR_ALPHA,
+            8,
+            vec![0; canvas_bytes],
+        )?;
+
+        for (position, frame) in self.frames.iter().enumerate() {
+            let control = &frame.control;
+            let decoded;
+            let pixels = if frame.is_default_image {
+                &default_image
+            } else {
+                decoded = self.decode_frame(frame)?;
+                &decoded
+            };
+            let region = |x: usize, y: usize| {
+                ((control.y_offset as usize + y) * width + control.x_offset as usize + x) * 4
+            };
+
+            // A first frame can not revert to a previous one, so it is cleared instead
+            let dispose_op = match control.dispose_op {
+                DisposeOp::Previous if position == 0 => DisposeOp::Background,
+                dispose_op => dispose_op,
+            };
+            let previous = (dispose_op == DisposeOp::Previous).then(|| canvas.data.clone());
+
+            for y in 0..control.height as usize {
+                for x in 0..control.width as usize {
+                    let source = (y * control.width as usize + x) * 4;
+                    let source = &pixels.data[source..source + 4];
+                    let target = &mut canvas.data[region(x, y)..region(x, y) + 4];
+                    match control.blend_op {
+                        BlendOp::Source => target.copy_from_slice(source),
+                        BlendOp::Over => blend_over(target, source),
+                    }
+                }
+            }
+
+            show(position, &canvas)?;
+
+            match (dispose_op, previous) {
+                (DisposeOp::Background, _) => {
+                    for y in 0..control.height as usize {
+                        canvas.data[region(0, y)..region(control.width as usize, y)].fill(0);
+                    }
+                }
+                (DisposeOp::Previous, Some(previous)) => canvas.data = previous,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Composites an 8-bit RGBA pixel over another
+fn blend_over(target: &mut [u8], source: &[u8]) {
+    let source_alpha = source[3] as u32;
+    let target_alpha = target[3] as u32 * (255 - source_alpha) / 255;
+    let alpha = source_alpha + target_alpha;
+    if alpha == 0 {
+        target.fill(0);
+        return;
+    }
+
+    for channel in 0..3 {
+        target[channel] = ((source[channel] as u32 * source_alpha
+            + target[channel] as u32 * target_alpha)
+            / alpha) as u8;
+    }
+    target[3] = alpha as u8;
+}
+
+/// Builds an animated png from images of the same size and format, each shown for its delay in
+/// milliseconds. The first image is also the default image.
+pub fn assemble(images: &[Image], delays: &[u16], num_plays: u32, level: u32) -> Result<Png> {
+    let first = images
+        .first()
+        .ok_or(anyhow!("an animation needs at least one frame"))?;
+    if delays.len() != images.len() {
+        bail!("got {} delays for {} frames", delays.len(), images.len())
+    }
+    if first.color_type == INDEXED {
+        bail!("indexed frames can not be joined, their palettes may differ")
+    }
+
+    let header = first.header();
+    let control = AnimationControl {
+        num_frames: images.len() as u32,
+        num_plays,
+    };
+    let mut chunks = vec![
+        Chunk::new(ChunkType::from_str("IHDR")?, header.to_bytes()),
+        Chunk::new(ChunkType::from_str("acTL")?, control.to_bytes()),
+    ];
+
+    let mut sequence_number = 0;
+    for (index, (image, delay)) in images.iter().zip(delays).enumerate() {
+        if image.header() != header {
+            bail!(
+                "frame {} is a {}x{} image of color type {} at {} bits, expected {}x{} of color type {} at {} bits",
+                index,
+                image.width,
+                image.height,
+                image.color_type,
+                image.bit_depth,
+                header.width,
+                header.height,
+                header.color_type,
+                header.bit_depth
+            )
+        }
+
+        let control = FrameControl {
+            sequence_number,
+            width: header.width,
+            height: header.height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: *delay,
+            delay_den: MILLISECONDS,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        chunks.push(Chunk::new(ChunkType::from_str("fcTL")?, control.to_bytes()));
+        sequence_number += 1;
+
+        let data = image.encode_image_data(level)?;
+        if index == 0 {
+            chunks.push(Chunk::new(ChunkType::from_str("IDAT")?, data));
+        } else {
+            chunks.push(Chunk::new(
+                ChunkType::from_str("fdAT")?,
+                join_frame_data(sequence_number, &data),
+            ));
+            sequence_number += 1;
+        }
+    }
+
+    chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+/// Decodes pngs for [`assemble`], along with the colour chunks they share. Frames keep their
+/// format if they all have the same one. Otherwise they are converted to 8-bit RGBA, which is
+/// refused for 16-bit frames rather than losing precision, and tRNS and sBIT are dropped since
+/// they no longer apply.
+pub fn join_frames(pngs: &[Png]) -> Result<(Vec<Image>, Vec<Chunk>)> {
+    let images = pngs.iter().map(Image::decode).collect::<Result<Vec<_>>>()?;
+    let header = images
+        .first()
+        .ok_or(anyhow!("an animation needs at least one frame"))?
+        .header();
+    let keep_format =
+        header.color_type != INDEXED && images.iter().all(|image| image.header() == header);
+
+    let mut color_chunks = Vec::new();
+    for chunk_type in COLOR_CHUNK_TYPES {
+        if !keep_format && ["sBIT", "tRNS"].contains(chunk_type) {
+            continue;
+        }
+        let chunk = pngs[0].chunk_by_type(chunk_type);
+        if let Some(index) = pngs.iter().position(|png| {
+            png.chunk_by_type(chunk_type).map(Chunk::data) != chunk.map(Chunk::data)
+        }) {
+            bail!(
+                "frame {} differs from the first frame in its [{}] chunk",
+                index,
+                chunk_type
+            )
+        }
+        color_chunks.extend(
+            chunk.map(|chunk| Chunk::new(chunk.chunk_type().clone(), chunk.data().to_vec())),
+        );
+    }
+    if keep_format {
+        return Ok((images, color_chunks));
+    }
+
+    if let Some(index) = images.iter().position(|image| image.bit_depth == 16) {
+        bail!(
+            "frames differ in format or are indexed, and converting them to 8-bit RGBA would \
+             lose the precision of 16-bit frame {}",
+            index
+        )
+    }
+    let images = pngs
+        .iter()
+        .zip(images)
+        .map(|(png, image)| {
+            image.to_rgba8(
+                png.chunk_by_type("PLTE").map(Chunk::data),
+                png.chunk_by_type("tRNS").map(Chunk::data),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((images, color_chunks))
+}
+
+fn check_bounds(frame: &FrameControl, header: &ImageHeader, index: usize) -> Result<()> {
+    let right = frame.x_offset as u64 + frame.width as u64;
+    let bottom = frame.y_offset as u64 + frame.height as u64;
+    if frame.width == 0
+        || frame.height == 0
+        || right > header.width as u64
+        || bottom > header.height as u64
+    {
+        bail!(
+            "frame {} ({}x{} at {},{}) does not fit the {}x{} image",
+            index,
+            frame.width,
+            frame.height,
+            frame.x_offset,
+            frame.y_offset,
+            header.width,
+            header.height
+        )
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_data::{BlendOp, DisposeOp};
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    pub(crate) fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn frame_control(sequence_number: u32, width: u32, x_offset: u32) -> Chunk {
+        let control = FrameControl {
+            sequence_number,
+            width,
+            height: 2,
+            x_offset,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        chunk("fcTL", control.to_bytes())
+    }
+
+    fn frame_data(sequence_number: u32) -> Chunk {
+        let mut data = sequence_number.to_be_bytes().to_vec();
+        data.extend_from_slice(b"zlib");
+        chunk("fdAT", data)
+    }
+
+    /// A 4x2 animation whose default image is the first of three frames
+    fn testing_chunks() -> Vec<Chunk> {
+        let header = ImageHeader {
+            width: 4,
+            height: 2,
+            bit_depth: 8,
+            color_type: 6,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let control = AnimationControl {
+            num_frames: 3,
+            num_plays: 0,
+        };
+
+        vec![
+            chunk("IHDR", header.to_bytes()),
+            chunk("acTL", control.to_bytes()),
+            frame_control(0, 4, 0),
+            chunk("IDAT", b"zlib".to_vec()),
+            frame_control(1, 2, 2),
+            frame_data(2),
+            frame_data(3),
+            frame_control(4, 1, 0),
+            frame_data(5),
+            chunk("IEND", Vec::new()),
+        ]
+    }
+
+    #[test]
+    fn test_frames() {
+        let png = Png::from_chunks(testing_chunks());
+        let apng = Apng::new(&png).unwrap();
+
+        assert!(Apng::is_animated(&png));
+        assert!(apng.default_image_is_frame());
+        assert_eq!(apng.control().num_frames, 3);
+
+        let frames = apng.frames();
+        assert_eq!(frames[0].data_chunks, [3]);
+        assert_eq!(frames[1].data_chunks, [5, 6]);
+        assert_eq!(frames[1].control.x_offset, 2);
+        assert_eq!(frames[2].data_chunks, [8]);
+        assert_eq!(frames[2].delay, 0.1);
+    }
+
+    fn rendered(png: &Png) -> Result<Vec<Image>> {
+        let mut frames = Vec::new();
+        Apng::new(png)?.render(|_, canvas| {
+            frames.push(canvas.clone());
+            Ok(())
+        })?;
+        Ok(frames)
+    }
+
+    fn solid(width: u32, height: u32, rgba: [u8; 4]) -> Image {
+        let data = rgba.repeat((width * height) as usize);
+        Image::new(width, height, TRUECOLOR_ALPHA, 8, data).unwrap()
+    }
+
+    #[test]
+    fn test_assemble_round_trip() {
+        let images = vec![
+            solid(3, 2, [255, 0, 0, 255]),
+            solid(3, 2, [0, 255, 0, 128]),
+            solid(3, 2, [0, 0, 255, 0]),
+        ];
+        let png = assemble(&images, &[100, 250, 40], 2, 6).unwrap();
+
+        let apng = Apng::new(&png).unwrap();
+        assert_eq!(apng.control().num_plays, 2);
+        assert_eq!(apng.frames()[1].delay, 0.25);
+        assert_eq!(rendered(&png).unwrap(), images);
+
+        let bytes = png.as_bytes();
+        let image = crate::image::decode_rgba8(&Png::try_from(bytes.as_slice()).unwrap());
+        assert_eq!(image.unwrap(), images[0]);
+
+        assert!(assemble(&images, &[100], 0, 6).is_err());
+        assert!(assemble(&[images[0].clone(), solid(2, 2, [0; 4])], &[1, 1], 0, 6).is_err());
+    }
+
+    #[test]
+    fn test_render_composites() {
+        let region = |control: FrameControl, image: &Image| {
+            vec![
+                chunk("fcTL", control.to_bytes()),
+                chunk(
+                    "fdAT",
+                    join_frame_data(
+                        control.sequence_number + 1,
+                        &image.encode_image_data(6).unwrap(),
+                    ),
+                ),
+            ]
+        };
+        let base = assemble(&[solid(2, 2, [255, 0, 0, 255])], &[10], 0, 6).unwrap();
+        let mut chunks: Vec<Chunk> = base
+            .chunks()
+            .iter()
+            .map(|existing| chunk(&existing.chunk_type().to_string(), existing.data().to_vec()))
+            .collect();
+        let end = chunks.pop().unwrap();
+        chunks[1] = chunk(
+            "acTL",
+            AnimationControl {
+                num_frames: 3,
+                num_plays: 0,
+            }
+            .to_bytes(),
+        );
+
+        let over = FrameControl {
+            sequence_number: 1,
+            width: 1,
+            height: 1,
+            x_offset: 1,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::Background,
+            blend_op: BlendOp::Over,
+        };
+        chunks.extend(region(over, &solid(1, 1, [0, 0, 255, 128])));
+        let source = FrameControl {
+            sequence_number: 3,
+            x_offset: 0,
+            y_offset: 1,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+            ..over
+        };
+        chunks.extend(region(source, &solid(1, 1, [0, 255, 0, 255])));
+        chunks.push(end);
+
+        let png = Png::from_chunks(chunks);
+        let frames = rendered(&png).unwrap();
+
+        let red = [255, 0, 0, 255];
+        assert_eq!(&frames[1].data[4..8], [127, 0, 128, 255]);
+        assert_eq!(&frames[1].data[0..4], red);
+        // the blended pixel was cleared to transparent after frame 1
+        assert_eq!(&frames[2].data[4..8], [0, 0, 0, 0]);
+        assert_eq!(&frames[2].data[8..12], [0, 255, 0, 255]);
+        assert_eq!(&frames[2].data[12..16], red);
+    }
+
+    #[test]
+    fn test_join_frames() {
+        let gray = |value: u16, gamma: u8| {
+            let image = Image::new(2, 1, 0, 16, [value.to_be_bytes(); 2].concat()).unwrap();
+            let mut png = image.to_png(6).unwrap();
+            png.insert_chunk(1, chunk("gAMA", vec![0, 0, 177, gamma]));
+            png
+        };
+
+        let (images, color_chunks) = join_frames(&[gray(1000, 143), gray(2000, 143)]).unwrap();
+        assert_eq!((images[1].color_type, images[1].bit_depth), (0, 16));
+        assert_eq!(images[1].samples(), [2000, 2000]);
+        assert_eq!(color_chunks.len(), 1);
+        assert_eq!(color_chunks[0].chunk_type().to_string(), "gAMA");
+
+        assert!(join_frames(&[gray(1000, 143), gray(2000, 144)]).is_err());
+
+        let rgba = || solid(2, 1, [1, 2, 3, 255]).to_png(6).unwrap();
+        assert!(join_frames(&[gray(1000, 143), rgba()]).is_err());
+
+        let mut indexed = Image::new(2, 1, INDEXED, 8, vec![0, 1])
+            .unwrap()
+            .to_png(6)
+            .unwrap();
+        indexed.insert_chunk(1, chunk("PLTE", vec![9, 9, 9, 1, 2, 3]));
+        indexed.insert_chunk(2, chunk("tRNS", vec![0]));
+        let (images, color_chunks) = join_frames(&[indexed, rgba()]).unwrap();
+        assert_eq!(images[0].data, [9, 9, 9, 0, 1, 2, 3, 255]);
+        assert!(color_chunks.is_empty());
+    }
+
+    #[test]
+    fn test_render_hostile_dimensions() {
+        let side = 100_000;
+        let header = ImageHeader {
+            width: side,
+            height: side,
+            ..solid(4, 2, [0; 4]).header()
+        };
+        let control = FrameControl {
+            sequence_number: 0,
+            width: side,
+            height: side,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", header.to_bytes()),
+            chunk(
+                "acTL",
+                AnimationControl {
+                    num_frames: 1,
+                    num_plays: 0,
+                }
+                .to_bytes(),
+            ),
+            chunk("fcTL", control.to_bytes()),
+            chunk("IDAT", solid(4, 2, [0; 4]).encode_image_data(6).unwrap()),
+            chunk("IEND", Vec::new()),
+        ]);
+
+        assert!(rendered(&png).is_err());
+    }
+
+    #[test]
+    fn test_invalid_sequence() {
+        let mut chunks = testing_chunks();
+        chunks[6] = frame_data(7);
+        let png = Png::from_chunks(chunks);
+        assert!(Apng::new(&png).is_err());
+    }
+
+    #[test]
+    fn test_frame_out_of_bounds() {
+        let mut chunks = testing_chunks();
+        chunks[4] = frame_control(1, 3, 2);
+        let png = Png::from_chunks(chunks);
+        assert!(Apng::new(&png).is_err());
+    }
+
+    #[test]
+    fn test_frame_count_mismatch() {
+        let mut chunks = testing_chunks();
+        chunks.drain(7..9);
+        let png = Png::from_chunks(chunks);
+        assert!(Apng::new(&png).is_err());
+
+        let png = Png::from_chunks(vec![chunk("IHDR", testing_chunks()[0].data().to_vec())]);
+        assert!(!Apng::is_animated(&png));
+    }
+}
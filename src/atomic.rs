@@ -0,0 +1,178 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Context};
+
+use crate::Result;
+
+/// Suffix appended to the file name of the original when `--backup` is given without one
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".bak";
+
+/// Distinguishes temporary files created by concurrent writes within this process
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+fn sibling(path: &Path, name: String) -> PathBuf {
+    path.with_file_name(name)
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or(anyhow!("{} is not a file path", path.display()))
+}
+
+/// Path of the backup kept for `path`
+pub fn backup_path(path: &Path, suffix: &str) -> Result<PathBuf> {
+    Ok(sibling(path, format!("{}{}", file_name(path)?, suffix)))
+}
+
+/// Replaces the contents of `path` with `data` so that a crash leaves either the old or the new
+/// file, never a mix. The data is written to a temporary file in the same directory, synced and
+/// renamed over `path`. With `backup`, the original is first kept under its name plus that suffix.
+pub fn write(path: &Path, data: &[u8], backup: Option<&str>) -> Result<()> {
+    let temporary = sibling(
+        path,
+        format!(
+            ".{}.{}-{}.tmp",
+            file_name(path)?,
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ),
+    );
+
+    let result = write_temporary(&temporary, path, data).and_then(|()| {
+        if let (Some(suffix), true) = (backup, path.exists()) {
+            keep_backup(path, &backup_path(path, suffix)?)?;
+        }
+        std::fs::rename(&temporary, path)
+            .with_context(|| format!("could not replace {}", path.display()))?;
+        sync_directory(path)
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
+
+fn write_temporary(temporary: &Path, path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temporary)
+        .with_context(|| format!("could not create {}", temporary.display()))?;
+    file.write_all(data)?;
+
+    if let Ok(metadata) = std::fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Links the original to the backup path, copying it where hard links are not supported
+fn keep_backup(path: &Path, backup: &Path) -> Result<()> {
+    if backup.exists() {
+        std::fs::remove_file(backup)?;
+    }
+    if std::fs::hard_link(path, backup).is_err() {
+        std::fs::copy(path, backup)
+            .with_context(|| format!("could not back up to {}", backup.display()))?;
+    }
+    Ok(())
+}
+
+/// Makes the rename durable. Directories can not be opened for syncing on every platform, so
+/// failures are ignored.
+fn sync_directory(path: &Path) -> Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pngme-atomic-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_replace_truncates() {
+        let dir = testing_dir("truncate");
+        let path = dir.join("image.png");
+        std::fs::write(&path, b"a much longer original").unwrap();
+
+        write(&path, b"short", None).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"short");
+        assert_eq!(entries(&dir), ["image.png"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup() {
+        let dir = testing_dir("backup");
+        let path = dir.join("image.png");
+        std::fs::write(&path, b"original").unwrap();
+
+        write(&path, b"first", Some(DEFAULT_BACKUP_SUFFIX)).unwrap();
+        write(&path, b"second", Some(".orig")).unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert_eq!(
+            std::fs::read(dir.join("image.png.bak")).unwrap(),
+            b"original"
+        );
+        assert_eq!(std::fs::read(dir.join("image.png.orig")).unwrap(), b"first");
+        assert_eq!(
+            entries(&dir),
+            ["image.png", "image.png.bak", "image.png.orig"]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_new_file_without_backup() {
+        let dir = testing_dir("new");
+        let path = dir.join("new.png");
+
+        write(&path, b"data", Some(DEFAULT_BACKUP_SUFFIX)).unwrap();
+        assert_eq!(entries(&dir), ["new.png"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_failure_leaves_no_temporary_file() {
+        let dir = testing_dir("failure");
+        let path = dir.join("missing").join("image.png");
+
+        assert!(write(&path, b"data", None).is_err());
+        assert!(entries(&dir).is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::Result;
+
+/// Extension of the files picked up when walking a directory
+pub const PNG_EXTENSION: &str = "png";
+
+/// How command line paths are expanded into files and processed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Batch {
+    /// Descend into subdirectories of directory arguments
+    pub recursive: bool,
+    /// Number of worker threads, 0 to use one per cpu
+    pub jobs: usize,
+}
+
+/// Result of running a command on one file
+#[derive(Debug, Serialize)]
+pub struct FileResult<T> {
+    pub file_path: PathBuf,
+    #[serde(flatten)]
+    pub outcome: Outcome<T>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome<T> {
+    Ok { output: T },
+    Error { error: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchOutput<T> {
+    pub files: Vec<FileResult<T>>,
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+impl Batch {
+    /// True if `paths` names a single file rather than a directory or pattern, so the command
+    /// runs in its usual single file mode. Files that do not exist yet count as single files.
+    pub fn is_single(paths: &[PathBuf]) -> bool {
+        match paths {
+            [path] => path.is_file() || (!path.is_dir() && !is_pattern(path)),
+            _ => false,
+        }
+    }
+
+    /// Expands files, directories and glob patterns into a sorted list of files
+    pub fn expand(&self, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for path in paths {
+            if path.is_dir() {
+                self.walk(path, &mut files)?;
+            } else if path.is_file() {
+                files.push(path.clone());
+            } else if is_pattern(path) {
+                let pattern = path.to_string_lossy();
+                let matches = glob::glob(&pattern)?
+                    .collect::<std::result::Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .filter(|path| path.is_file());
+                let before = files.len();
+                files.extend(matches);
+                if files.len() == before {
+                    bail!("pattern {} matches no files", pattern)
+                }
+            } else {
+                bail!("file {} does not exist", path.display())
+            }
+        }
+
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// Pairs every file `paths` expand to with a path under `output` mirroring where it was
+    /// found: relative to its directory argument, or by file name for files and glob matches.
+    /// Fails if two files would be written to the same path.
+    pub fn mirror(&self, paths: &[PathBuf], output: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut pairs = Vec::new();
+        for path in paths {
+            for file in self.expand(std::slice::from_ref(path))? {
+                let relative = if path.is_dir() {
+                    file.strip_prefix(path)?.to_path_buf()
+                } else {
+                    PathBuf::from(
+                        file.file_name()
+                            .ok_or(anyhow!("{} has no file name", file.display()))?,
+                    )
+                };
+                pairs.push((file, output.join(relative)));
+            }
+        }
+        pairs.sort();
+        pairs.dedup_by(|a, b| a.0 == b.0);
+
+        let mut targets: Vec<&PathBuf> = pairs.iter().map(|(_, target)| target).collect();
+        targets.sort();
+        if let Some(pair) = targets.windows(2).find(|pair| pair[0] == pair[1]) {
+            bail!("several files would be written to {}", pair[0].display())
+        }
+        Ok(pairs)
+    }
+
+    fn walk(&self, directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let mut entries = std::fs::read_dir(directory)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        entries.sort();
+
+        for path in entries {
+            if path.is_dir() {
+                if self.recursive {
+                    self.walk(&path, files)?;
+                }
+            } else if path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(PNG_EXTENSION))
+            {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `command` on every file in parallel, collecting each file's outcome in order
+    pub fn run<T, F>(&self, files: Vec<PathBuf>, command: F) -> Result<BatchOutput<T>>
+    where
+        T: Send,
+        F: Fn(PathBuf) -> Result<T> + Sync,
+    {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .build()
+            .map_err(|error| anyhow!("could not start worker threads: {}", error))?;
+
+        let files: Vec<FileResult<T>> = pool.install(|| {
+            files
+                .into_par_iter()
+                .map(|file_path| FileResult {
+                    outcome: match command(file_path.clone()) {
+                        Ok(output) => Outcome::Ok { output },
+                        Err(error) => Outcome::Error {
+                            error: format!("{:#}", error),
+                        },
+                    },
+                    file_path,
+                })
+                .collect()
+        });
+
+        let failed = files
+            .iter()
+            .filter(|file| matches!(file.outcome, Outcome::Error { .. }))
+            .count();
+
+        Ok(BatchOutput {
+            succeeded: files.len() - failed,
+            failed,
+            files,
+        })
+    }
+}
+
+impl<T> BatchOutput<T> {
+    pub fn is_success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for BatchOutput<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            match &file.outcome {
+                Outcome::Ok { output } => {
+                    writeln!(f, "== {} ==\n{}", file.file_path.display(), output)?
+                }
+                Outcome::Error { error } => {
+                    writeln!(f, "== {} ==\nerror: {}", file.file_path.display(), error)?
+                }
+            }
+        }
+        write!(
+            f,
+            "{} files: {} succeeded, {} failed",
+            self.files.len(),
+            self.succeeded,
+            self.failed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_tree(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("pngme-batch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        for file in ["a.png", "b.PNG", "notes.txt", "nested/c.png"] {
+            std::fs::write(root.join(file), file).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_is_single() {
+        assert!(Batch::is_single(&[PathBuf::from("missing.png")]));
+        assert!(!Batch::is_single(&[PathBuf::from("*.png")]));
+        assert!(!Batch::is_single(&[std::env::temp_dir()]));
+        assert!(!Batch::is_single(&[
+            PathBuf::from("a.png"),
+            PathBuf::from("b.png")
+        ]));
+    }
+
+    #[test]
+    fn test_expand_directory() {
+        let root = testing_tree("directory");
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|file| file.strip_prefix(&root).unwrap().display().to_string())
+                .collect()
+        };
+
+        let flat = Batch::default()
+            .expand(std::slice::from_ref(&root))
+            .unwrap();
+        assert_eq!(names(flat), ["a.png", "b.PNG"]);
+
+        let recursive = Batch {
+            recursive: true,
+            jobs: 0,
+        };
+        let all = recursive.expand(std::slice::from_ref(&root)).unwrap();
+        assert_eq!(names(all), ["a.png", "b.PNG", "nested/c.png"]);
+
+        let globbed = Batch::default()
+            .expand(&[root.join("*/*.png"), root.join("a.png")])
+            .unwrap();
+        assert_eq!(names(globbed), ["a.png", "nested/c.png"]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_mirror() {
+        let root = testing_tree("mirror");
+        let output = PathBuf::from("out");
+        let recursive = Batch {
+            recursive: true,
+            jobs: 0,
+        };
+
+        let pairs = recursive
+            .mirror(std::slice::from_ref(&root), &output)
+            .unwrap();
+        let targets: Vec<&PathBuf> = pairs.iter().map(|(_, target)| target).collect();
+        assert_eq!(
+            targets,
+            [
+                &output.join("a.png"),
+                &output.join("b.PNG"),
+                &output.join("nested/c.png")
+            ]
+        );
+
+        std::fs::write(root.join("nested/a.png"), "a.png").unwrap();
+        assert!(recursive
+            .mirror(&[root.join("a.png"), root.join("nested/a.png")], &output)
+            .is_err());
+        assert_eq!(
+            recursive
+                .mirror(std::slice::from_ref(&root), &output)
+                .unwrap()
+                .len(),
+            4
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_run_collects_failures() {
+        let files = vec![PathBuf::from("1"), PathBuf::from("x"), PathBuf::from("3")];
+        let batch = Batch {
+            recursive: false,
+            jobs: 2,
+        };
+        let output = batch
+            .run(files, |path| Ok(path.to_string_lossy().parse::<u32>()?))
+            .unwrap();
+
+        assert_eq!((output.succeeded, output.failed), (2, 1));
+        assert!(matches!(output.files[0].outcome, Outcome::Ok { output: 1 }));
+        assert!(matches!(output.files[1].outcome, Outcome::Error { .. }));
+        assert!(!output.is_success());
+    }
+}
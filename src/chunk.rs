@@ -6,10 +6,40 @@ use std::fmt::Display;
 use crate::chunk_type::ChunkType;
 use crate::{Error, Result};
 
+/// Crc engine shared by every chunk, so the lookup table is built once
+static CRC: Crc<u32> = Crc::<u32>::new(Chunk::CRC_ALGORITHM);
+
+/// How chunks are checked while parsing
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Compare each chunk's stored crc with its data. When off, the stored crc is kept and can be
+    /// checked later with `verify_crc`.
+    pub verify_crc: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self { verify_crc: true }
+    }
+}
+
+/// How chunks are written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Serialisation {
+    /// Write parsed chunks with the crc they were read with, even if it does not match, so
+    /// parsing and writing a file reproduces it byte for byte
+    AsRead,
+    /// Write every chunk with a freshly computed crc
+    Canonical,
+}
+
 pub struct Chunk {
     chunk_type: ChunkType,
     data: Vec<u8>,
-    crc: Crc<u32>,
+    /// Crc read from the file, if the chunk was parsed rather than created
+    stored_crc: Option<u32>,
+    /// Offset the chunk was read from
+    offset: Option<usize>,
 }
 
 impl Chunk {
@@ -21,11 +51,20 @@ impl Chunk {
     pub const CRC_ALGORITHM: &Algorithm<u32> = &crc::CRC_32_ISO_HDLC;
 
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Self {
-        let crc = crc::Crc::<u32>::new(Chunk::CRC_ALGORITHM);
         Self {
             chunk_type,
             data,
-            crc,
+            stored_crc: None,
+            offset: None,
+        }
+    }
+
+    /// Creates a chunk that [`Serialisation::AsRead`] writes with `crc`, even if it does not
+    /// match the data
+    pub fn with_crc(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Self {
+        Self {
+            stored_crc: Some(crc),
+            ..Chunk::new(chunk_type, data)
         }
     }
 
@@ -49,33 +88,85 @@ impl Chunk {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_bytes(Serialisation::Canonical)
+    }
+
+    pub fn to_bytes(&self, serialisation: Serialisation) -> Vec<u8> {
+        let crc = match (serialisation, self.stored_crc) {
+            (Serialisation::AsRead, Some(stored_crc)) => stored_crc,
+            _ => self.crc(),
+        };
         let data_length = self.length() as u32;
         data_length
             .to_be_bytes()
             .iter()
             .chain(self.chunk_type.bytes().iter())
             .chain(self.data.iter())
-            .chain(self.crc().to_be_bytes().iter())
+            .chain(crc.to_be_bytes().iter())
             .copied()
             .collect()
     }
 
-    fn crc(&self) -> u32 {
-        let bytes: Vec<u8> = self
-            .chunk_type
-            .bytes()
-            .iter()
-            .chain(self.data.iter())
-            .copied()
-            .collect();
+    pub fn crc(&self) -> u32 {
+        Chunk::checksum(&self.chunk_type, &self.data)
+    }
+
+    pub fn stored_crc(&self) -> Option<u32> {
+        self.stored_crc
+    }
 
-        self.crc.checksum(&bytes)
+    /// Offset of the chunk in the file it was parsed from
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Checks the stored crc against the data, for chunks parsed without verification
+    pub fn verify_crc(&self) -> Result<()> {
+        match self.stored_crc {
+            Some(stored_crc) => check_crc(&self.chunk_type, stored_crc, self.crc()),
+            None => Ok(()),
+        }
+    }
+
+    /// Computes the crc of a chunk with the given type and data
+    pub fn checksum(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        let mut digest = CRC.digest();
+        digest.update(&chunk_type.bytes());
+        digest.update(data);
+        digest.finalize()
     }
 }
 
-impl TryFrom<&[u8]> for Chunk {
-    type Error = Error;
-    fn try_from(value: &[u8]) -> Result<Self> {
+fn check_crc(chunk_type: &ChunkType, stored_crc: u32, actual_crc: u32) -> Result<()> {
+    if stored_crc != actual_crc {
+        bail!(
+            "Invalid crc for [{}]: [{}] != [{}]",
+            chunk_type,
+            stored_crc,
+            actual_crc
+        )
+    }
+    Ok(())
+}
+
+/// A chunk borrowed from the buffer it was parsed from, such as a memory-mapped file
+#[derive(Debug, Clone)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    stored_crc: u32,
+    offset: usize,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Parses the chunk whose length field starts at `offset` in `bytes`, checking its type and crc
+    pub fn parse(bytes: &'a [u8], offset: usize) -> Result<Self> {
+        ChunkRef::parse_with(bytes, offset, ParseOptions::default())
+    }
+
+    /// Like [`ChunkRef::parse`], skipping crc verification unless `options` asks for it
+    pub fn parse_with(bytes: &'a [u8], offset: usize, options: ParseOptions) -> Result<Self> {
+        let value = bytes.get(offset..).unwrap_or_default();
         if value.len() < Chunk::METADATA_BYTES {
             bail!("input length lower than metadata length")
         }
@@ -92,24 +183,84 @@ impl TryFrom<&[u8]> for Chunk {
         if !chunk_type.is_valid() {
             bail!("chunk type [{}] is invalid", chunk_type)
         }
-        let (chunk_data, value) = value.split_at(data_length);
+        if value.len() < data_length + Chunk::CRC_BYTES {
+            bail!("chunk [{}] is truncated", chunk_type)
+        }
+        let (data, value) = value.split_at(data_length);
 
         let (crc_bytes, _) = value.split_at(Chunk::CRC_BYTES);
 
-        let new = Self {
-            chunk_type: chunk_type,
-            data: chunk_data.into(),
-            crc: Crc::<u32>::new(Chunk::CRC_ALGORITHM),
+        let chunk = Self {
+            chunk_type,
+            data,
+            stored_crc: u32::from_be_bytes(crc_bytes.try_into()?),
+            offset,
         };
 
-        let actual_crc = new.crc();
-        let expected_crc = u32::from_be_bytes(crc_bytes.try_into()?);
+        if options.verify_crc {
+            chunk.verify_crc()?;
+        }
+
+        Ok(chunk)
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn length(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn stored_crc(&self) -> u32 {
+        self.stored_crc
+    }
+
+    /// Offset of the chunk's length field in the buffer it was parsed from
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
 
-        if expected_crc != actual_crc {
-            bail!("Invalid crc: [{}] != [{}]", expected_crc, actual_crc)
+    pub fn computed_crc(&self) -> u32 {
+        Chunk::checksum(&self.chunk_type, self.data)
+    }
+
+    pub fn verify_crc(&self) -> Result<()> {
+        check_crc(&self.chunk_type, self.stored_crc, self.computed_crc())
+    }
+
+    /// Copies the chunk data into an owned chunk
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::from(self.clone())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = Error;
+    fn try_from(value: &'a [u8]) -> Result<Self> {
+        ChunkRef::parse(value, 0)
+    }
+}
+
+impl From<ChunkRef<'_>> for Chunk {
+    fn from(chunk: ChunkRef<'_>) -> Self {
+        Chunk {
+            chunk_type: chunk.chunk_type,
+            data: chunk.data.to_vec(),
+            stored_crc: Some(chunk.stored_crc),
+            offset: Some(chunk.offset),
         }
+    }
+}
 
-        Ok(new)
+impl TryFrom<&[u8]> for Chunk {
+    type Error = Error;
+    fn try_from(value: &[u8]) -> Result<Self> {
+        ChunkRef::try_from(value).map(Chunk::from)
     }
 }
 
@@ -248,4 +399,38 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_chunk_ref() {
+        let mut bytes = vec![0xaa, 0xbb];
+        bytes.extend(testing_chunk().as_bytes());
+
+        let chunk = ChunkRef::parse(&bytes, 2).unwrap();
+        assert_eq!(chunk.offset(), 2);
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.stored_crc(), 2882656334);
+        assert_eq!(chunk.data().as_ptr(), bytes[10..].as_ptr());
+        assert_eq!(chunk.to_chunk().as_bytes(), &bytes[2..]);
+
+        assert!(ChunkRef::parse(&bytes[..bytes.len() - 1], 2).is_err());
+        assert!(ChunkRef::parse(&bytes, 1).is_err());
+    }
+
+    #[test]
+    fn test_deferred_crc_verification() {
+        let mut bytes = testing_chunk().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(ChunkRef::parse(&bytes, 0).is_err());
+
+        let options = ParseOptions { verify_crc: false };
+        let chunk = ChunkRef::parse_with(&bytes, 0, options).unwrap().to_chunk();
+        assert_eq!(chunk.stored_crc(), Some(2882656334 ^ 1));
+        assert_eq!(chunk.crc(), 2882656334);
+        assert!(chunk.verify_crc().is_err());
+        assert!(testing_chunk().verify_crc().is_ok());
+
+        assert_eq!(chunk.to_bytes(Serialisation::AsRead), bytes);
+        assert_eq!(chunk.as_bytes(), testing_chunk().as_bytes());
+    }
 }
@@ -0,0 +1,519 @@
+use std::fmt;
+
+use anyhow::bail;
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::{rewrite, Result};
+
+/// Compressed text is not inflated past this many bytes
+pub const MAX_INFLATED_TEXT_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Decoded contents of a chunk type pngme understands
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ChunkData {
+    ImageHeader(ImageHeader),
+    Palette {
+        entries: Vec<[u8; 3]>,
+    },
+    Gamma {
+        gamma: u32,
+    },
+    Chromaticities {
+        white_point: [u32; 2],
+        red: [u32; 2],
+        green: [u32; 2],
+        blue: [u32; 2],
+    },
+    StandardRgb {
+        rendering_intent: u8,
+    },
+    PhysicalDimensions {
+        pixels_per_unit_x: u32,
+        pixels_per_unit_y: u32,
+        unit: u8,
+    },
+    Time {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+    Text {
+        keyword: String,
+        text: String,
+    },
+    CompressedText {
+        keyword: String,
+        text: String,
+    },
+    InternationalText {
+        keyword: String,
+        compressed: bool,
+        language: String,
+        translated_keyword: String,
+        text: String,
+    },
+    AnimationControl(AnimationControl),
+    FrameControl(FrameControl),
+    FrameData {
+        sequence_number: u32,
+        length: usize,
+    },
+}
+
+/// Contents of the IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+impl ImageHeader {
+    pub const LENGTH: usize = 13;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() != ImageHeader::LENGTH {
+            bail!(
+                "IHDR must be {} bytes, got {}",
+                ImageHeader::LENGTH,
+                data.len()
+            )
+        }
+
+        Ok(Self {
+            width: be_u32(&data[0..4]),
+            height: be_u32(&data[4..8]),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.width
+            .to_be_bytes()
+            .iter()
+            .chain(self.height.to_be_bytes().iter())
+            .chain(
+                [
+                    self.bit_depth,
+                    self.color_type,
+                    self.compression_method,
+                    self.filter_method,
+                    self.interlace_method,
+                ]
+                .iter(),
+            )
+            .copied()
+            .collect()
+    }
+}
+
+/// Contents of the acTL chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+    /// Number of times to loop, 0 to loop forever
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub const LENGTH: usize = 8;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let data = expect_length(data, AnimationControl::LENGTH)?;
+        Ok(Self {
+            num_frames: be_u32(&data[0..4]),
+            num_plays: be_u32(&data[4..8]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.num_frames, self.num_plays]
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+}
+
+/// How the frame area is treated before the next frame is rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisposeOp {
+    /// Leave the frame as is
+    None,
+    /// Clear the frame area to transparent black
+    Background,
+    /// Revert the frame area to what it was before the frame
+    Previous,
+}
+
+/// How the frame is combined with the output buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendOp {
+    /// Replace the frame area
+    Source,
+    /// Alpha composite the frame over the frame area
+    Over,
+}
+
+impl fmt::Display for DisposeOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DisposeOp::None => "none",
+            DisposeOp::Background => "background",
+            DisposeOp::Previous => "previous",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for BlendOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BlendOp::Source => "source",
+            BlendOp::Over => "over",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Contents of the fcTL chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    pub const LENGTH: usize = 26;
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let data = expect_length(data, FrameControl::LENGTH)?;
+        Ok(Self {
+            sequence_number: be_u32(&data[0..4]),
+            width: be_u32(&data[4..8]),
+            height: be_u32(&data[8..12]),
+            x_offset: be_u32(&data[12..16]),
+            y_offset: be_u32(&data[16..20]),
+            delay_num: u16::from_be_bytes([data[20], data[21]]),
+            delay_den: u16::from_be_bytes([data[22], data[23]]),
+            dispose_op: match data[24] {
+                0 => DisposeOp::None,
+                1 => DisposeOp::Background,
+                2 => DisposeOp::Previous,
+                op => bail!("unknown fcTL dispose op {}", op),
+            },
+            blend_op: match data[25] {
+                0 => BlendOp::Source,
+                1 => BlendOp::Over,
+                op => bail!("unknown fcTL blend op {}", op),
+            },
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+        ]
+        .iter()
+        .flat_map(|value| value.to_be_bytes())
+        .chain(self.delay_num.to_be_bytes())
+        .chain(self.delay_den.to_be_bytes())
+        .chain([self.dispose_op as u8, self.blend_op as u8])
+        .collect()
+    }
+
+    /// Frame delay in seconds; a denominator of 0 means hundredths of a second
+    pub fn delay(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 / den as f64
+    }
+}
+
+/// Splits fdAT data into its sequence number and the image data it carries
+pub fn split_frame_data(data: &[u8]) -> Result<(u32, &[u8])> {
+    if data.len() < 4 {
+        bail!("fdAT is shorter than its sequence number")
+    }
+    Ok((be_u32(&data[0..4]), &data[4..]))
+}
+
+/// Prefixes image data with a sequence number to form fdAT data
+pub fn join_frame_data(sequence_number: u32, data: &[u8]) -> Vec<u8> {
+    sequence_number
+        .to_be_bytes()
+        .iter()
+        .chain(data)
+        .copied()
+        .collect()
+}
+
+impl ChunkData {
+    /// Decodes the data of `chunk`, returning `None` for chunk types without a typed representation
+    pub fn parse(chunk: &Chunk) -> Result<Option<ChunkData>> {
+        ChunkData::parse_bytes(&chunk.chunk_type().bytes(), chunk.data())
+    }
+
+    pub fn parse_bytes(chunk_type: &[u8; 4], data: &[u8]) -> Result<Option<ChunkData>> {
+        let parsed = match chunk_type {
+            b"IHDR" => ChunkData::ImageHeader(ImageHeader::from_bytes(data)?),
+            b"PLTE" => {
+                if !data.len().is_multiple_of(3) {
+                    bail!("PLTE length {} is not a multiple of 3", data.len())
+                }
+                ChunkData::Palette {
+                    entries: data.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect(),
+                }
+            }
+            b"gAMA" => ChunkData::Gamma {
+                gamma: be_u32(expect_length(data, 4)?),
+            },
+            b"cHRM" => {
+                let data = expect_length(data, 32)?;
+                let point = |index: usize| {
+                    [
+                        be_u32(&data[index * 8..index * 8 + 4]),
+                        be_u32(&data[index * 8 + 4..index * 8 + 8]),
+                    ]
+                };
+                ChunkData::Chromaticities {
+                    white_point: point(0),
+                    red: point(1),
+                    green: point(2),
+                    blue: point(3),
+                }
+            }
+            b"sRGB" => ChunkData::StandardRgb {
+                rendering_intent: expect_length(data, 1)?[0],
+            },
+            b"pHYs" => {
+                let data = expect_length(data, 9)?;
+                ChunkData::PhysicalDimensions {
+                    pixels_per_unit_x: be_u32(&data[0..4]),
+                    pixels_per_unit_y: be_u32(&data[4..8]),
+                    unit: data[8],
+                }
+            }
+            b"tIME" => {
+                let data = expect_length(data, 7)?;
+                ChunkData::Time {
+                    year: u16::from_be_bytes([data[0], data[1]]),
+                    month: data[2],
+                    day: data[3],
+                    hour: data[4],
+                    minute: data[5],
+                    second: data[6],
+                }
+            }
+            b"tEXt" => {
+                let (keyword, text) = split_keyword(data)?;
+                ChunkData::Text {
+                    keyword,
+                    text: latin1(text),
+                }
+            }
+            b"zTXt" => {
+                let (keyword, rest) = split_keyword(data)?;
+                if rest.first() != Some(&0) {
+                    bail!("zTXt uses an unknown compression method")
+                }
+                ChunkData::CompressedText {
+                    keyword,
+                    text: latin1(&inflate(&rest[1..])?),
+                }
+            }
+            b"iTXt" => {
+                let (keyword, rest) = split_keyword(data)?;
+                if rest.len() < 2 {
+                    bail!("iTXt is truncated")
+                }
+                let compressed = rest[0] == 1;
+                let (language, rest) = split_at_nul(&rest[2..])?;
+                let (translated_keyword, text) = split_at_nul(rest)?;
+                let text = if compressed {
+                    inflate(text)?
+                } else {
+                    text.to_vec()
+                };
+                ChunkData::InternationalText {
+                    keyword,
+                    compressed,
+                    language: String::from_utf8(language.to_vec())?,
+                    translated_keyword: String::from_utf8(translated_keyword.to_vec())?,
+                    text: String::from_utf8(text)?,
+                }
+            }
+            b"acTL" => ChunkData::AnimationControl(AnimationControl::from_bytes(data)?),
+            b"fcTL" => ChunkData::FrameControl(FrameControl::from_bytes(data)?),
+            b"fdAT" => {
+                let (sequence_number, data) = split_frame_data(data)?;
+                ChunkData::FrameData {
+                    sequence_number,
+                    length: data.len(),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(parsed))
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn expect_length(data: &[u8], length: usize) -> Result<&[u8]> {
+    if data.len() != length {
+        bail!(
+            "expected {} bytes of chunk data, got {}",
+            length,
+            data.len()
+        )
+    }
+    Ok(data)
+}
+
+fn split_at_nul(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    match data.iter().position(|byte| *byte == 0) {
+        Some(index) => Ok((&data[..index], &data[index + 1..])),
+        None => bail!("missing null separator"),
+    }
+}
+
+fn split_keyword(data: &[u8]) -> Result<(String, &[u8])> {
+    let (keyword, rest) = split_at_nul(data)?;
+    Ok((latin1(keyword), rest))
+}
+
+/// Text chunks other than iTXt are encoded as ISO 8859-1
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    rewrite::inflate(data, MAX_INFLATED_TEXT_LENGTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_image_header_round_trip() {
+        let data = [0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0];
+        let header = ImageHeader::from_bytes(&data).unwrap();
+
+        assert_eq!(header.width, 50);
+        assert_eq!(header.height, 40);
+        assert_eq!(header.color_type, 6);
+        assert_eq!(header.to_bytes(), data);
+    }
+
+    #[test]
+    fn test_parse_text() {
+        let parsed = ChunkData::parse(&chunk("tEXt", b"Author\0Ferris")).unwrap();
+        assert_eq!(
+            parsed,
+            Some(ChunkData::Text {
+                keyword: "Author".to_string(),
+                text: "Ferris".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_physical_dimensions() {
+        let parsed = ChunkData::parse(&chunk("pHYs", &[0, 0, 14, 194, 0, 0, 14, 194, 1])).unwrap();
+        assert_eq!(
+            parsed,
+            Some(ChunkData::PhysicalDimensions {
+                pixels_per_unit_x: 3778,
+                pixels_per_unit_y: 3778,
+                unit: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_frame_control_round_trip() {
+        let data = [
+            0, 0, 0, 1, 0, 0, 0, 16, 0, 0, 0, 8, 0, 0, 0, 2, 0, 0, 0, 4, 0, 5, 0, 0, 2, 1,
+        ];
+        let control = FrameControl::from_bytes(&data).unwrap();
+
+        assert_eq!(control.sequence_number, 1);
+        assert_eq!((control.width, control.height), (16, 8));
+        assert_eq!((control.x_offset, control.y_offset), (2, 4));
+        assert_eq!(control.delay(), 0.05);
+        assert_eq!(control.dispose_op, DisposeOp::Previous);
+        assert_eq!(control.blend_op, BlendOp::Over);
+        assert_eq!(control.to_bytes(), data);
+
+        let mut invalid = data;
+        invalid[25] = 2;
+        assert!(FrameControl::from_bytes(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_parse_animation_chunks() {
+        assert_eq!(
+            ChunkData::parse(&chunk("acTL", &[0, 0, 0, 3, 0, 0, 0, 0])).unwrap(),
+            Some(ChunkData::AnimationControl(AnimationControl {
+                num_frames: 3,
+                num_plays: 0
+            }))
+        );
+        assert_eq!(
+            ChunkData::parse(&chunk("fdAT", &[0, 0, 0, 7, 1, 2, 3])).unwrap(),
+            Some(ChunkData::FrameData {
+                sequence_number: 7,
+                length: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_unknown() {
+        assert_eq!(ChunkData::parse(&chunk("ruSt", b"data")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_length() {
+        assert!(ChunkData::parse(&chunk("gAMA", &[0, 0, 1])).is_err());
+    }
+}
@@ -5,7 +5,7 @@ use std::str::FromStr;
 
 use anyhow::bail;
 
-use crate::util::is_set;
+use crate::util::{is_set, with_bit};
 use crate::{Error, Result};
 
 /// A validated PNG chunk type. See the PNG spec for more details.
@@ -17,29 +17,82 @@ pub struct ChunkType {
 }
 
 impl ChunkType {
+    /// Bit 5 of each byte, which encodes the chunk properties. It is set for lowercase letters.
+    pub const PROPERTY_BIT: u8 = 0b00100000;
+
+    /// Builds a chunk type from a four letter name, adjusting the case of each letter so the
+    /// type has the requested properties. The reserved bit is always cleared.
+    pub fn with_properties(
+        name: &str,
+        critical: bool,
+        public: bool,
+        safe_to_copy: bool,
+    ) -> Result<Self> {
+        let bytes: [u8; 4] = name.as_bytes().try_into()?;
+        if !Self::is_valid_bytes(bytes) {
+            bail!("ChunkType::with_properties name is invalid")
+        }
+
+        Ok(Self {
+            chunk_bytes: [
+                with_bit(bytes[0], Self::PROPERTY_BIT, !critical),
+                with_bit(bytes[1], Self::PROPERTY_BIT, !public),
+                with_bit(bytes[2], Self::PROPERTY_BIT, false),
+                with_bit(bytes[3], Self::PROPERTY_BIT, safe_to_copy),
+            ],
+        })
+    }
+
     /// Returns the raw bytes contained in this chunk
     pub fn bytes(&self) -> [u8; 4] {
         self.chunk_bytes
     }
 
-    /// Returns the property state of the first byte as described in the PNG spec
+    /// Returns true if the property bit of the byte at `index` is set
+    fn is_property_bit_set(&self, index: usize) -> bool {
+        is_set(self.chunk_bytes[index], Self::PROPERTY_BIT)
+    }
+
+    /// Critical chunks have an uppercase first letter (ancillary bit clear)
     pub fn is_critical(&self) -> bool {
-        is_set(self.chunk_bytes[0], 0b00100000)
+        !self.is_property_bit_set(0)
     }
 
-    /// Returns the property state of the second byte as described in the PNG spec
+    /// Public chunks have an uppercase second letter (private bit clear)
     pub fn is_public(&self) -> bool {
-        is_set(self.chunk_bytes[1], 0b00100000)
+        !self.is_property_bit_set(1)
     }
 
-    /// Returns the property state of the third byte as described in the PNG spec
+    /// The reserved bit must be clear, i.e. the third letter must be uppercase
     pub fn is_reserved_bit_valid(&self) -> bool {
-        is_set(self.chunk_bytes[2], 0b00100000)
+        !self.is_property_bit_set(2)
     }
 
-    /// Returns the property state of the fourth byte as described in the PNG spec
+    /// Safe-to-copy chunks have a lowercase fourth letter (safe-to-copy bit set)
     pub fn is_safe_to_copy(&self) -> bool {
-        !is_set(self.chunk_bytes[3], 0b00100000)
+        self.is_property_bit_set(3)
+    }
+
+    /// Returns the chunk properties as a comma separated list, e.g. `ancillary, public, safe-to-copy`
+    pub fn properties(&self) -> String {
+        [
+            if self.is_critical() {
+                "critical"
+            } else {
+                "ancillary"
+            },
+            if self.is_public() {
+                "public"
+            } else {
+                "private"
+            },
+            if self.is_safe_to_copy() {
+                "safe-to-copy"
+            } else {
+                "unsafe-to-copy"
+            },
+        ]
+        .join(", ")
     }
 
     /// Returns true if the reserved byte is valid and all four bytes are represented by the characters A-Z or a-z.
@@ -180,6 +233,22 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_with_properties() {
+        let chunk = ChunkType::with_properties("rust", false, false, true).unwrap();
+        assert_eq!(&chunk.to_string(), "ruSt");
+        assert!(!chunk.is_critical());
+        assert!(!chunk.is_public());
+        assert!(chunk.is_safe_to_copy());
+        assert!(chunk.is_valid());
+
+        let chunk = ChunkType::with_properties("rust", true, true, false).unwrap();
+        assert_eq!(&chunk.to_string(), "RUST");
+
+        assert!(ChunkType::with_properties("ru5t", true, true, true).is_err());
+        assert!(ChunkType::with_properties("rusty", true, true, true).is_err());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -1,12 +1,42 @@
 use std::{
-    io::{Read, Seek, Write},
-    path::PathBuf,
+    collections::HashMap,
+    fmt::Display,
+    io::{Read, Write},
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
-use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Result};
-use anyhow::{anyhow, bail};
-use clap::{arg, command, Parser, Subcommand};
+use crate::{
+    apng::{self, Apng},
+    atomic,
+    batch::Batch,
+    chunk::{Chunk, ParseOptions, Serialisation},
+    chunk_type::ChunkType,
+    compare,
+    diff::{self, PngDiff},
+    dump::PngDump,
+    exif,
+    hexdump::{self, ChunkFilter},
+    image, inplace,
+    output::{
+        render, ChunkList, ChunkListing, CompareOutput, DecodeOutput, EncodeOutput, ErrorOutput,
+        ExifField, ExifOutput, ExifRemoveOutput, Exit, FramesOutput, HexdumpOutput,
+        RecompressOutput, RemoveOutput, RemovedChunk, RepairOutput, SplitOutput, StripOutput,
+        TrailerAction, TrailerOutput, WriteOutput,
+    },
+    png::{Png, PngRef},
+    registry,
+    repair::{self, RepairOptions},
+    rewrite, scan, spread,
+    strip::{encoded_size, StripPolicy},
+    trailer, Result,
+};
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+/// Path standing for standard input, or standard output where a png is written
+pub const STDIO_PATH: &str = "-";
 
 #[derive(Parser)]
 #[command(name = "pngme")]
@@ -14,6 +44,25 @@ use clap::{arg, command, Parser, Subcommand};
 #[command(author, version)]
 #[command(propagate_version = true)]
 struct PngMe {
+    /// Print results and errors as json documents
+    #[arg(long, global = true)]
+    json: bool,
+    /// Descend into subdirectories of directories given as --file-path
+    #[arg(long, global = true)]
+    recursive: bool,
+    /// Number of files processed in parallel, 0 for one per cpu
+    #[arg(long, global = true, default_value_t = 0)]
+    jobs: usize,
+    /// Keep the original of every modified file under its name plus this suffix
+    #[arg(
+        long,
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = atomic::DEFAULT_BACKUP_SUFFIX,
+        value_name = "SUFFIX"
+    )]
+    backup: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -21,112 +70,890 @@ struct PngMe {
 #[derive(Subcommand)]
 enum Commands {
     Encode {
-        #[arg(long)]
-        file_path: std::path::PathBuf,
+        /// Png to add the chunk to, `-` for standard input. Can also be given as the first
+        /// positional argument.
+        #[arg(long, required_unless_present = "paths")]
+        file_path: Vec<std::path::PathBuf>,
         #[arg(long)]
         message: String,
         #[arg(long)]
         chunk_type: String,
-        output_file: Option<std::path::PathBuf>,
+        /// File to write instead of modifying the input file. Without --file-path, the input file
+        /// comes first, e.g. `encode - --stdout` or `encode in.png out.png`.
+        #[arg(value_name = "OUTPUT_FILE", num_args = 1..=2)]
+        paths: Vec<std::path::PathBuf>,
+        /// Replace an existing file that is not a png with a new png carrying the chunk
+        #[arg(long)]
+        force: bool,
+        /// Write the resulting png to standard output instead of modifying the file
+        #[arg(long)]
+        stdout: bool,
+        /// Overwrite IEND with the new chunk instead of rewriting the whole file. Fast on large
+        /// files, but not atomic.
+        #[arg(long, conflicts_with_all = ["stdout", "force"])]
+        in_place: bool,
     },
     Decode {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
         #[arg(long)]
-        file_path: std::path::PathBuf,
+        chunk_type: String,
+    },
+    Remove {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
         #[arg(long)]
         chunk_type: String,
+        /// Write the resulting png to standard output instead of modifying the file
+        #[arg(long)]
+        stdout: bool,
+        /// Truncate the file at the chunk instead of rewriting it. Only works for the last chunk
+        /// before IEND and is not atomic.
+        #[arg(long, conflicts_with = "stdout")]
+        in_place: bool,
+    },
+    List {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+    },
+    /// Remove non-essential chunks. Without a policy flag only critical chunks are kept.
+    Strip {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        /// Remove every ancillary chunk
+        #[arg(long)]
+        keep_critical_only: bool,
+        /// Chunk types to keep regardless of the other policies, e.g. sRGB,gAMA,iCCP
+        #[arg(long, value_delimiter = ',')]
+        keep: Vec<String>,
+        /// Remove every private chunk
+        #[arg(long)]
+        remove_private: bool,
+        /// List the chunks that would be removed without modifying the file
+        #[arg(long)]
+        dry_run: bool,
+        /// Write the resulting png to standard output instead of modifying the file
+        #[arg(long)]
+        stdout: bool,
+    },
+    /// Re-compress the image data into a single IDAT chunk
+    Recompress {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        /// zlib compression level, 0-9
+        #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: u32,
+        /// Keep ancillary chunks that are not safe to copy after the image data changes
+        #[arg(long)]
+        keep_unsafe: bool,
+        /// Write the resulting png to standard output instead of modifying the file
+        #[arg(long)]
+        stdout: bool,
+    },
+    /// Print the chunk structure as json or yaml
+    Dump {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        #[arg(long, value_enum, default_value_t = DumpFormat::Json)]
+        format: DumpFormat,
+        output_file: Option<std::path::PathBuf>,
+    },
+    /// Print an annotated hex dump of the file
+    Hexdump {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        /// Only dump chunks of this type
+        #[arg(long)]
+        chunk: Option<String>,
+        /// Only dump the chunk at this index, counted among chunks of --chunk if given
+        #[arg(long)]
+        index: Option<usize>,
+    },
+    /// Compare the chunks of two png files
+    Diff {
+        old_file: std::path::PathBuf,
+        new_file: std::path::PathBuf,
+    },
+    /// Report the data appended after IEND, optionally extracting, stripping or replacing it
+    Trailer {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        /// Write the trailer to this file
+        #[arg(long, conflicts_with_all = ["strip", "write"])]
+        extract: Option<std::path::PathBuf>,
+        /// Remove the trailer from the png
+        #[arg(long, conflicts_with = "write")]
+        strip: bool,
+        /// Replace the trailer with the contents of this file
+        #[arg(long)]
+        write: Option<std::path::PathBuf>,
     },
+    /// Score png files for signs of hidden data
+    Scan {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+    },
+    /// List the frames of an animated png
+    Frames {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+    },
+    /// Show or scrub the EXIF data of the eXIf chunk
+    Exif {
+        #[command(subcommand)]
+        command: ExifCommands,
+    },
+    /// Split an animated png into frames or join pngs into one
+    Apng {
+        #[command(subcommand)]
+        command: ApngCommands,
+    },
+    /// Compare the decoded pixels of two png files
+    Compare {
+        old_file: std::path::PathBuf,
+        new_file: std::path::PathBuf,
+        /// Write a grayscale png showing where the pixels differ
+        #[arg(long)]
+        diff_map: Option<std::path::PathBuf>,
+        /// Exit with status 2 if any sample differs by more than this
+        #[arg(long)]
+        max_difference: Option<u32>,
+        /// Exit with status 2 if the psnr in dB is below this
+        #[arg(long)]
+        min_psnr: Option<f64>,
+    },
+    /// Salvage a damaged png into a new file, logging each fix
+    Repair {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        /// Keep chunks with a wrong crc and correct it, instead of dropping them. Required when a
+        /// critical chunk has a wrong crc.
+        #[arg(long)]
+        fix_crc: bool,
+        /// Also write the log of fixes to this file
+        #[arg(long)]
+        log: Option<std::path::PathBuf>,
+        /// The repaired file, or the directory to write repaired files to when given several,
+        /// keeping their paths below any directory given as --file-path
+        output_file: std::path::PathBuf,
+    },
+    /// Rebuild a png from the output of dump
+    Build {
+        #[arg(long)]
+        file_path: std::path::PathBuf,
+        /// Defaults to yaml for .yaml/.yml files and json otherwise
+        #[arg(long, value_enum)]
+        format: Option<DumpFormat>,
+        output_file: std::path::PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExifCommands {
+    /// List the tags of the eXIf chunk
+    Show {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+    },
+    /// Remove the eXIf chunk, or only its GPS data
     Remove {
+        #[arg(long, required = true)]
+        file_path: Vec<std::path::PathBuf>,
+        /// Keep the eXIf chunk and remove only the GPS IFD, leaving every other byte of it alone
+        #[arg(long)]
+        gps_only: bool,
+        /// Write the resulting png to standard output instead of modifying the file
+        #[arg(long)]
+        stdout: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApngCommands {
+    /// Write every frame, composited as it is displayed, to its own png
+    Split {
         #[arg(long)]
         file_path: std::path::PathBuf,
+        /// Directory to write frame-000.png, frame-001.png, ... to
+        output_dir: std::path::PathBuf,
+        /// zlib compression level, 0-9
+        #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: u32,
+    },
+    /// Build an animated png from pngs of the same size, in the order given. Frames of one format
+    /// keep it; frames of differing formats or with palettes are converted to 8-bit RGBA, which is
+    /// refused for 16-bit frames. Colour chunks (cHRM, gAMA, iCCP, sBIT, sRGB, tRNS) must match
+    /// across frames and are kept, except sBIT and tRNS after a conversion.
+    Join {
+        #[arg(long = "frame", required = true)]
+        frames: Vec<std::path::PathBuf>,
+        /// Delay of each frame in milliseconds, or a single delay for every frame
+        #[arg(long, value_delimiter = ',', default_value = "100")]
+        delay: Vec<u16>,
+        /// Number of times to play the animation, 0 to loop forever
+        #[arg(long, default_value_t = 0)]
+        plays: u32,
+        /// zlib compression level, 0-9
+        #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: u32,
+        output_file: std::path::PathBuf,
+    },
+    /// Hide a message across the frames of an animated png, one chunk after each frame's data
+    Hide {
+        #[arg(long)]
+        file_path: std::path::PathBuf,
+        #[arg(long)]
+        message: String,
+        /// Ancillary chunk type carrying the parts
         #[arg(long)]
         chunk_type: String,
+        /// Write to this file instead of modifying the input file
+        output_file: Option<std::path::PathBuf>,
     },
-    List {
+    /// Recover a message hidden with apng hide
+    Reveal {
         #[arg(long)]
         file_path: std::path::PathBuf,
+        #[arg(long)]
+        chunk_type: String,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpFormat {
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
 pub fn execute() -> Result<()> {
     let command = PngMe::parse();
+    let json = command.json;
+    let batch = Batch {
+        recursive: command.recursive,
+        jobs: command.jobs,
+    };
+
+    match run(command.command, json, batch, command.backup) {
+        Ok(output) => {
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+            Ok(())
+        }
+        Err(error) if error.is::<Exit>() => {
+            let exit = error.downcast::<Exit>()?;
+            println!("{}", exit.output);
+            std::process::exit(exit.code);
+        }
+        Err(error) if json => {
+            println!("{}", render(&ErrorOutput::from(&error), true)?);
+            std::process::exit(1);
+        }
+        Err(error) => Err(error),
+    }
+}
 
-    let output = match command.command {
+fn run(command: Commands, json: bool, batch: Batch, backup: Option<String>) -> Result<String> {
+    let file = Destination::File {
+        backup: backup.as_deref(),
+    };
+
+    match command {
         Commands::Encode {
             file_path,
             message,
             chunk_type,
-            output_file,
-        } => encode_chunk(file_path, message, chunk_type, output_file),
+            paths,
+            force,
+            stdout,
+            in_place,
+        } => match encode_paths(file_path, paths, stdout || in_place)? {
+            (file_path, Some(output_file)) => render(
+                &encode_chunk(
+                    single(file_path)?,
+                    message,
+                    chunk_type,
+                    Some(output_file),
+                    force,
+                    file,
+                )?,
+                json,
+            ),
+            (file_path, None) if stdout => to_stderr(
+                &encode_chunk(
+                    single(file_path)?,
+                    message,
+                    chunk_type,
+                    None,
+                    force,
+                    Destination::Stdout,
+                )?,
+                json,
+            ),
+            (file_path, None) => {
+                let destination = if in_place {
+                    in_place_destination(&backup)?
+                } else {
+                    file
+                };
+                for_each_file(file_path, batch, json, |file_path| {
+                    encode_chunk(
+                        file_path,
+                        message.clone(),
+                        chunk_type.clone(),
+                        None,
+                        force,
+                        destination,
+                    )
+                })
+            }
+        },
         Commands::Decode {
             file_path,
             chunk_type,
-        } => decode_chunk(file_path, chunk_type),
+        } => for_each_file(file_path, batch, json, |file_path| {
+            decode_chunk(file_path, chunk_type.clone())
+        }),
+        Commands::Remove {
+            file_path,
+            chunk_type,
+            stdout: true,
+            ..
+        } => to_stderr(
+            &remove_chunk(single(file_path)?, chunk_type, Destination::Stdout)?,
+            json,
+        ),
         Commands::Remove {
             file_path,
             chunk_type,
-        } => remove_chunk(file_path, chunk_type),
-        Commands::List { file_path } => list_chunks(file_path),
-    }?;
+            stdout: false,
+            in_place,
+        } => {
+            let destination = if in_place {
+                in_place_destination(&backup)?
+            } else {
+                file
+            };
+            for_each_file(file_path, batch, json, |file_path| {
+                remove_chunk(file_path, chunk_type.clone(), destination)
+            })
+        }
+        Commands::List { file_path, format } => match format {
+            ListFormat::Csv if !json => for_each_file(file_path, batch, false, |file_path| {
+                Ok(list_chunks(file_path)?.to_csv())
+            }),
+            ListFormat::Json => for_each_file(file_path, batch, true, list_chunks),
+            _ => for_each_file(file_path, batch, json, list_chunks),
+        },
+        Commands::Strip {
+            file_path,
+            keep_critical_only,
+            keep,
+            remove_private,
+            dry_run,
+            stdout: true,
+        } => to_stderr(
+            &strip_chunks(
+                single(file_path)?,
+                keep_critical_only,
+                keep,
+                remove_private,
+                dry_run,
+                Destination::Stdout,
+            )?,
+            json,
+        ),
+        Commands::Strip {
+            file_path,
+            keep_critical_only,
+            keep,
+            remove_private,
+            dry_run,
+            stdout: false,
+        } => for_each_file(file_path, batch, json, |file_path| {
+            strip_chunks(
+                file_path,
+                keep_critical_only,
+                keep.clone(),
+                remove_private,
+                dry_run,
+                file,
+            )
+        }),
+        Commands::Recompress {
+            file_path,
+            level,
+            keep_unsafe,
+            stdout: true,
+        } => to_stderr(
+            &recompress_image(single(file_path)?, level, keep_unsafe, Destination::Stdout)?,
+            json,
+        ),
+        Commands::Recompress {
+            file_path,
+            level,
+            keep_unsafe,
+            stdout: false,
+        } => for_each_file(file_path, batch, json, |file_path| {
+            recompress_image(file_path, level, keep_unsafe, file)
+        }),
+        Commands::Dump {
+            file_path,
+            format,
+            output_file: Some(output_file),
+        } => {
+            let document = dump_png(single(file_path)?, format)?;
+            render(&write_file(output_file, document.as_bytes())?, json)
+        }
+        Commands::Dump {
+            file_path,
+            format,
+            output_file: None,
+        } => match Batch::is_single(&file_path) {
+            true => dump_png(single(file_path)?, format),
+            false => for_each_file(file_path, batch, json, |file_path| {
+                dump_png(file_path, format)
+            }),
+        },
+        Commands::Build {
+            file_path,
+            format,
+            output_file,
+        } => render(&build_png(file_path, format, output_file)?, json),
+        Commands::Hexdump {
+            file_path,
+            chunk,
+            index,
+        } => for_each_file(file_path, batch, json, |file_path| {
+            hexdump(file_path, chunk.clone(), index)
+        }),
+        Commands::Diff { old_file, new_file } => render(&diff_pngs(old_file, new_file)?, json),
+        Commands::Repair {
+            file_path,
+            fix_crc,
+            log,
+            output_file,
+        } => {
+            let options = RepairOptions { fix_crc };
+            let rendered = if Batch::is_single(&file_path) {
+                render(&repair_png(single(file_path)?, options, output_file)?, json)
+            } else {
+                let targets: HashMap<PathBuf, PathBuf> = batch
+                    .mirror(&file_path, &output_file)?
+                    .into_iter()
+                    .collect();
+                for_each_file(file_path, batch, json, |file_path| {
+                    let repaired = targets[&file_path].clone();
+                    if let Some(parent) = repaired.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    repair_png(file_path, options, repaired)
+                })
+            };
+            // a batch with failures still has a log to write
+            let text = match &rendered {
+                Ok(rendered) => Some(rendered.as_str()),
+                Err(error) => error
+                    .downcast_ref::<Exit>()
+                    .map(|exit| exit.output.as_str()),
+            };
+            if let (Some(log), Some(text)) = (log, text) {
+                atomic::write(&log, format!("{}\n", text).as_bytes(), None)?;
+            }
+            rendered
+        }
+        Commands::Trailer {
+            file_path,
+            extract: Some(extract),
+            ..
+        } => render(
+            &edit_trailer(single(file_path)?, Some(extract), false, None, file)?,
+            json,
+        ),
+        Commands::Trailer {
+            file_path,
+            extract: None,
+            strip,
+            write,
+        } => for_each_file(file_path, batch, json, |file_path| {
+            edit_trailer(file_path, None, strip, write.clone(), file)
+        }),
+        Commands::Scan { file_path } => for_each_file(file_path, batch, json, |file_path| {
+            Ok(scan::scan(&read_input(&file_path)?))
+        }),
+        Commands::Frames { file_path } => for_each_file(file_path, batch, json, list_frames),
+        Commands::Exif {
+            command: ExifCommands::Show { file_path },
+        } => for_each_file(file_path, batch, json, show_exif),
+        Commands::Exif {
+            command:
+                ExifCommands::Remove {
+                    file_path,
+                    gps_only,
+                    stdout: true,
+                },
+        } => to_stderr(
+            &remove_exif(single(file_path)?, gps_only, Destination::Stdout)?,
+            json,
+        ),
+        Commands::Exif {
+            command:
+                ExifCommands::Remove {
+                    file_path,
+                    gps_only,
+                    stdout: false,
+                },
+        } => for_each_file(file_path, batch, json, |file_path| {
+            remove_exif(file_path, gps_only, file)
+        }),
+        Commands::Apng {
+            command:
+                ApngCommands::Split {
+                    file_path,
+                    output_dir,
+                    level,
+                },
+        } => render(&split_apng(file_path, output_dir, level)?, json),
+        Commands::Apng {
+            command:
+                ApngCommands::Join {
+                    frames,
+                    delay,
+                    plays,
+                    level,
+                    output_file,
+                },
+        } => render(
+            &join_apng(frames, delay, plays, level, output_file, file)?,
+            json,
+        ),
+        Commands::Apng {
+            command:
+                ApngCommands::Hide {
+                    file_path,
+                    message,
+                    chunk_type,
+                    output_file,
+                },
+        } => render(
+            &hide_in_frames(file_path, message, chunk_type, output_file, file)?,
+            json,
+        ),
+        Commands::Apng {
+            command:
+                ApngCommands::Reveal {
+                    file_path,
+                    chunk_type,
+                },
+        } => render(&reveal_from_frames(file_path, chunk_type)?, json),
+        Commands::Compare {
+            old_file,
+            new_file,
+            diff_map,
+            max_difference,
+            min_psnr,
+        } => {
+            let output = compare_pngs(old_file, new_file, diff_map, max_difference, min_psnr)?;
+            let rendered = render(&output, json)?;
+            if output.within_threshold {
+                Ok(rendered)
+            } else {
+                Err(Exit {
+                    output: rendered,
+                    code: 2,
+                }
+                .into())
+            }
+        }
+    }
+}
 
-    println!("{}", output);
+/// Runs `command` on a single file as usual, or on every file the paths expand to. A batch in
+/// which any file fails exits with status 1 after printing the summary.
+fn for_each_file<T, F>(
+    file_paths: Vec<PathBuf>,
+    batch: Batch,
+    json: bool,
+    command: F,
+) -> Result<String>
+where
+    T: Serialize + Display + Send,
+    F: Fn(PathBuf) -> Result<T> + Sync,
+{
+    if Batch::is_single(&file_paths) {
+        return render(&command(single(file_paths)?)?, json);
+    }
 
-    Ok(())
+    let output = batch.run(batch.expand(&file_paths)?, command)?;
+    let rendered = render(&output, json)?;
+    if output.is_success() {
+        Ok(rendered)
+    } else {
+        Err(Exit {
+            output: rendered,
+            code: 1,
+        }
+        .into())
+    }
 }
 
-fn remove_chunk(file_path: PathBuf, chunk_type: String) -> Result<String> {
-    if !file_path.exists() {
-        return Err(anyhow!("file at the provided path does not exist"));
+/// Prints the report of a command whose png went to standard output
+fn to_stderr<T: Serialize + Display>(output: &T, json: bool) -> Result<String> {
+    eprintln!("{}", render(output, json)?);
+    Ok(String::new())
+}
+
+/// The only path in `file_paths`, for options that can not apply to several files
+/// Splits the positional arguments of `encode` into the input files and the output file. They
+/// are the output file alone when --file-path is given, otherwise the input file and an optional
+/// output file.
+fn encode_paths(
+    file_path: Vec<PathBuf>,
+    mut paths: Vec<PathBuf>,
+    output_conflicts: bool,
+) -> Result<(Vec<PathBuf>, Option<PathBuf>)> {
+    let (file_path, output_file) = match (file_path.is_empty(), paths.len()) {
+        (false, 2) => {
+            bail!("an output file was given twice, the input file comes from --file-path")
+        }
+        (false, _) => (file_path, paths.pop()),
+        (true, _) => {
+            let output_file = (paths.len() == 2).then(|| paths.remove(1));
+            (paths, output_file)
+        }
+    };
+
+    if output_file.is_some() && output_conflicts {
+        bail!("an output file cannot be combined with --stdout or --in-place")
     }
+    Ok((file_path, output_file))
+}
 
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .read(true)
-        .open(file_path)?;
+fn single(file_paths: Vec<PathBuf>) -> Result<PathBuf> {
+    if !Batch::is_single(&file_paths) {
+        bail!("this command or option needs exactly one file")
+    }
+    Ok(file_paths.into_iter().next().unwrap())
+}
 
-    let mut png = Png::try_from(&mut file)?;
+fn remove_chunk(
+    file_path: PathBuf,
+    chunk_type: String,
+    destination: Destination,
+) -> Result<RemoveOutput> {
+    if let Destination::InPlace = destination {
+        let chunk = inplace::remove_last_chunk(&mut open_in_place(&file_path)?, &chunk_type)?;
+        return Ok(RemoveOutput {
+            chunk_type: chunk.chunk_type().to_string(),
+            message: chunk.data_as_string()?,
+        });
+    }
 
+    let mut png = read_png(&file_path)?;
     let chunk = png.remove_chunk(chunk_type.as_str())?;
+    write_png(&file_path, &png, destination)?;
+
+    Ok(RemoveOutput {
+        chunk_type: chunk.chunk_type().to_string(),
+        message: chunk.data_as_string()?,
+    })
+}
+
+fn list_chunks(file_path: PathBuf) -> Result<ChunkList> {
+    with_input(&file_path, list_chunks_in)
+}
+
+fn list_chunks_in(bytes: &[u8]) -> Result<ChunkList> {
+    let layout = Png::layout(bytes)?;
+    let chunks: Vec<ChunkListing> = layout
+        .iter()
+        .map(|layout| ChunkListing {
+            index: layout.index,
+            offset: layout.offset,
+            length: layout.length,
+            chunk_type: layout.chunk_type.to_string(),
+            stored_crc: layout.stored_crc,
+            crc_valid: layout.stored_crc == layout.computed_crc(bytes),
+            properties: layout.chunk_type.properties(),
+            description: registry::lookup(&layout.chunk_type)
+                .map(|known| known.description)
+                .unwrap_or("unregistered")
+                .to_string(),
+            specification: registry::lookup(&layout.chunk_type)
+                .map(|known| known.specification.to_string()),
+            summary: registry::describe(&layout.chunk_type),
+        })
+        .collect();
+
+    Ok(ChunkList {
+        data_size: chunks.iter().map(|chunk| chunk.length).sum(),
+        file_size: bytes.len(),
+        trailer_size: bytes.len() - Png::trailer_offset(&layout),
+        chunks,
+    })
+}
 
-    file.seek(std::io::SeekFrom::Start(0))?;
-    file.write_all(&png.as_bytes())?;
-    file.sync_all()?;
-    drop(file);
+fn list_frames(file_path: PathBuf) -> Result<FramesOutput> {
+    let png = read_png(&file_path)?;
+    let apng = Apng::new(&png)?;
 
-    Ok(format!(
-        "removed chunk: [{}]: [{}]",
-        chunk.chunk_type().to_string(),
-        chunk.data_as_string()?
-    ))
+    Ok(FramesOutput {
+        num_frames: apng.control().num_frames,
+        num_plays: apng.control().num_plays,
+        default_image_is_frame: apng.default_image_is_frame(),
+        frames: apng.frames().to_vec(),
+    })
 }
 
-fn list_chunks(file_path: PathBuf) -> Result<String> {
-    if !file_path.exists() {
-        return Err(anyhow!("file at the provided path does not exist"));
+fn show_exif(file_path: PathBuf) -> Result<ExifOutput> {
+    let exif = exif::read(&read_png(&file_path)?)?
+        .ok_or_else(|| anyhow!("{} has no eXIf chunk", file_path.display()))?;
+
+    Ok(ExifOutput {
+        byte_order: exif.byte_order,
+        fields: exif
+            .entries()
+            .map(|(ifd, entry)| ExifField {
+                ifd,
+                tag: entry.tag,
+                name: entry.name(ifd),
+                value: entry.value.clone(),
+            })
+            .collect(),
+    })
+}
+
+fn remove_exif(
+    file_path: PathBuf,
+    gps_only: bool,
+    destination: Destination,
+) -> Result<ExifRemoveOutput> {
+    let mut png = read_png(&file_path)?;
+
+    let removed_fields = if gps_only {
+        exif::remove_gps_fields(&mut png)?
+    } else {
+        // a malformed eXIf chunk is still removed, its fields just can not be counted
+        let fields = exif::read(&png)
+            .ok()
+            .flatten()
+            .map_or(0, |exif| exif.entries().count());
+        (exif::remove(&mut png) > 0).then_some(fields)
+    };
+
+    if removed_fields.is_some() || matches!(destination, Destination::Stdout) {
+        write_png(&file_path, &png, destination)?;
     }
-    let mut file = std::fs::OpenOptions::new().read(true).open(file_path)?;
 
-    let png = Png::try_from(&mut file)?;
+    Ok(ExifRemoveOutput {
+        gps_only,
+        changed: removed_fields.is_some(),
+        removed_fields: removed_fields.unwrap_or(0),
+    })
+}
 
-    png.chunks()
+fn split_apng(file_path: PathBuf, output_dir: PathBuf, level: u32) -> Result<SplitOutput> {
+    let png = read_png(&file_path)?;
+    let apng = Apng::new(&png)?;
+
+    std::fs::create_dir_all(&output_dir)
+        .with_context(|| format!("could not create {}", output_dir.display()))?;
+    let mut frames = Vec::with_capacity(apng.frames().len());
+    apng.render(|index, image| {
+        let frame_path = output_dir.join(format!("frame-{:03}.png", index));
+        frames.push(write_file(frame_path, &image.to_png(level)?.as_bytes())?);
+        Ok(())
+    })?;
+
+    Ok(SplitOutput { frames })
+}
+
+fn join_apng(
+    frames: Vec<PathBuf>,
+    delay: Vec<u16>,
+    plays: u32,
+    level: u32,
+    output_file: PathBuf,
+    destination: Destination,
+) -> Result<WriteOutput> {
+    let delays = match delay.as_slice() {
+        [delay] => vec![*delay; frames.len()],
+        _ => delay,
+    };
+    let pngs = frames
         .iter()
-        .for_each(|chunk| println!("{}", chunk.chunk_type().to_string()));
+        .map(|frame| read_png(frame))
+        .collect::<Result<Vec<_>>>()?;
+    let (images, color_chunks) = apng::join_frames(&pngs)?;
+
+    let mut png = apng::assemble(&images, &delays, plays, level)?;
+    // after IHDR, ahead of the image data they describe
+    for (offset, chunk) in color_chunks.into_iter().enumerate() {
+        png.insert_chunk(1 + offset, chunk);
+    }
+    write_png(&output_file, &png, destination)?;
 
-    Ok("".to_string())
+    Ok(WriteOutput {
+        file_path: output_file,
+        size: png.as_bytes().len(),
+    })
 }
 
-fn decode_chunk(file_path: PathBuf, chunk_type: String) -> Result<String> {
-    let mut file = std::fs::OpenOptions::new().read(true).open(file_path)?;
+fn hide_in_frames(
+    file_path: PathBuf,
+    message: String,
+    chunk_type: String,
+    output_file: Option<PathBuf>,
+    destination: Destination,
+) -> Result<EncodeOutput> {
+    let chunk_type = ChunkType::from_str(&chunk_type)?;
+    let png = spread::embed(&read_png(&file_path)?, &chunk_type, message.as_bytes())?;
 
-    let png = Png::try_from(&mut file)?;
+    let output_path = output_file.unwrap_or(file_path);
+    write_png(&output_path, &png, destination)?;
 
-    match png.chunk_by_type(&chunk_type) {
-        Some(chunk) => Ok(format!(
-            "{}: {}",
-            chunk.chunk_type().to_string(),
-            chunk.data_as_string()?
-        )),
-        None => Err(anyhow!("could not find chunk by type {}", chunk_type)),
-    }
+    Ok(EncodeOutput {
+        file_path: output_path,
+        chunk_type: chunk_type.to_string(),
+        length: message.len(),
+    })
+}
+
+fn reveal_from_frames(file_path: PathBuf, chunk_type: String) -> Result<DecodeOutput> {
+    let chunk_type = ChunkType::from_str(&chunk_type)?;
+    let payload = spread::extract(&read_png(&file_path)?, &chunk_type)?;
+
+    Ok(DecodeOutput {
+        chunk_type: chunk_type.to_string(),
+        message: String::from_utf8(payload)?,
+    })
+}
+
+fn decode_chunk(file_path: PathBuf, chunk_type: String) -> Result<DecodeOutput> {
+    with_input(&file_path, |bytes| {
+        let png = PngRef::try_from(bytes)?;
+        // earlier versions appended chunks after IEND, so look there when IEND ends the search
+        let chunk = match png.chunk_by_type(&chunk_type) {
+            Some(chunk) => chunk.to_chunk(),
+            None => trailer::legacy_chunks(png.trailer())
+                .into_iter()
+                .find(|chunk| chunk.chunk_type().to_string() == chunk_type)
+                .ok_or_else(|| anyhow!("could not find chunk by type {}", chunk_type))?,
+        };
+        Ok(DecodeOutput {
+            chunk_type: chunk.chunk_type().to_string(),
+            message: chunk.data_as_string()?,
+        })
+    })
 }
 
 fn encode_chunk(
@@ -134,58 +961,349 @@ fn encode_chunk(
     message: String,
     chunk_type: String,
     output_file: Option<PathBuf>,
-) -> Result<String> {
-    let mut output_path = file_path;
-    if output_file.is_some() {
-        output_path = output_file.unwrap()
+    force: bool,
+    destination: Destination,
+) -> Result<EncodeOutput> {
+    let chunk_type = ChunkType::from_str(&chunk_type)?;
+
+    let chunk = Chunk::new(chunk_type.clone(), message.into_bytes());
+    let length = chunk.length();
+
+    if let Destination::InPlace = destination {
+        inplace::append_chunks(&mut open_in_place(&file_path)?, &[chunk])?;
+        return Ok(EncodeOutput {
+            file_path,
+            chunk_type: chunk_type.to_string(),
+            length,
+        });
     }
 
-    let chunk_type = ChunkType::from_str(&chunk_type)?;
+    let existing = if is_stdio(&file_path) || file_path.exists() {
+        read_input(&file_path)?
+    } else {
+        Vec::new()
+    };
 
-    let chunk = Chunk::new(chunk_type, message.into_bytes());
+    let mut png = if existing.is_empty() {
+        image::minimal_png()?
+    } else {
+        match Png::try_from(existing.as_slice()) {
+            Ok(png) => png,
+            Err(_) if force => image::minimal_png()?,
+            Err(error) => {
+                return Err(error.context(format!(
+                    "{} is not a png, use --force to replace it with a new png",
+                    file_path.display()
+                )))
+            }
+        }
+    };
+    png.append_chunk(chunk);
 
-    let mut output = std::fs::OpenOptions::new()
-        .write(true)
-        .read(true)
-        .create(true)
-        .open(output_path.clone())?;
+    let output_path = output_file.unwrap_or(file_path);
+    write_png(&output_path, &png, destination)?;
+
+    Ok(EncodeOutput {
+        file_path: if matches!(destination, Destination::Stdout) {
+            PathBuf::from(STDIO_PATH)
+        } else {
+            output_path
+        },
+        chunk_type: chunk_type.to_string(),
+        length,
+    })
+}
+
+fn strip_chunks(
+    file_path: PathBuf,
+    keep_critical_only: bool,
+    keep: Vec<String>,
+    remove_private: bool,
+    dry_run: bool,
+    destination: Destination,
+) -> Result<StripOutput> {
+    let keep = keep
+        .iter()
+        .map(|chunk_type| ChunkType::from_str(chunk_type))
+        .collect::<Result<Vec<_>>>()?;
 
-    let mut buf = Vec::<u8>::new();
-    let expected = output.metadata()?.len() as usize;
+    let policy = StripPolicy {
+        keep_critical_only: keep_critical_only || !remove_private,
+        remove_private,
+        keep,
+    };
 
-    if expected != 0 {
-        println!("expected = {}, reading from file", expected);
-        let read = output.read_to_end(&mut buf)?;
+    let mut png = read_png(&file_path)?;
 
-        if read != expected {
-            bail!("did not fully read output file! {} != {}", read, expected)
-        }
+    if dry_run {
+        return Ok(strip_output(true, &policy.matching(&png)));
     }
 
-    let buf: &[u8] = buf.as_ref();
+    let removed = policy.apply(&mut png);
+    write_png(&file_path, &png, destination)?;
+
+    Ok(strip_output(false, &removed.iter().collect::<Vec<_>>()))
+}
 
-    let png = match Png::try_from(buf) {
-        Ok(mut png) => {
-            png.append_chunk(chunk);
-            png
+fn strip_output(dry_run: bool, removed: &[&Chunk]) -> StripOutput {
+    StripOutput {
+        dry_run,
+        removed: removed
+            .iter()
+            .map(|chunk| RemovedChunk::from(*chunk))
+            .collect(),
+        bytes_saved: encoded_size(removed.iter().copied()),
+    }
+}
+
+fn recompress_image(
+    file_path: PathBuf,
+    level: u32,
+    keep_unsafe: bool,
+    destination: Destination,
+) -> Result<RecompressOutput> {
+    let mut png = read_png(&file_path)?;
+
+    let before = png.as_bytes().len();
+    let report = rewrite::recompress(&mut png, level, keep_unsafe)?;
+    let data = png.as_bytes();
+    write_png(&file_path, &png, destination)?;
+
+    Ok(RecompressOutput {
+        discarded: report
+            .discarded
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect(),
+        original_size: before,
+        new_size: data.len(),
+    })
+}
+
+fn dump_png(file_path: PathBuf, format: DumpFormat) -> Result<String> {
+    let png = Png::parse(&read_input(&file_path)?, ParseOptions { verify_crc: false })?;
+
+    let dump = PngDump::from_png(&png);
+    match format {
+        DumpFormat::Json => dump.to_json(),
+        DumpFormat::Yaml => dump.to_yaml(),
+    }
+}
+
+fn build_png(
+    file_path: PathBuf,
+    format: Option<DumpFormat>,
+    output_file: PathBuf,
+) -> Result<WriteOutput> {
+    let text = String::from_utf8(read_input(&file_path)?)?;
+
+    let format = format.unwrap_or_else(|| {
+        match file_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("yaml" | "yml") => DumpFormat::Yaml,
+            _ => DumpFormat::Json,
         }
-        Err(_) => Png::from_chunks(vec![chunk]),
+    });
+
+    let dump = match format {
+        DumpFormat::Json => PngDump::from_json(&text)?,
+        DumpFormat::Yaml => PngDump::from_yaml(&text)?,
+    };
+
+    write_file(output_file, &dump.to_png()?.to_bytes(Serialisation::AsRead))
+}
+
+fn write_file(file_path: PathBuf, data: &[u8]) -> Result<WriteOutput> {
+    atomic::write(&file_path, data, None)?;
+    Ok(WriteOutput {
+        file_path,
+        size: data.len(),
+    })
+}
+
+fn hexdump(
+    file_path: PathBuf,
+    chunk: Option<String>,
+    index: Option<usize>,
+) -> Result<HexdumpOutput> {
+    let bytes = read_input(&file_path)?;
+
+    let filter = ChunkFilter {
+        chunk_type: chunk,
+        index,
     };
+    let regions = hexdump::regions(&bytes, &filter)?;
+    if regions.is_empty() {
+        bail!("no chunk matches the given filter")
+    }
+
+    Ok(HexdumpOutput { regions })
+}
+
+/// Reads a whole file, or standard input when the path is `-`
+fn read_input(file_path: &Path) -> Result<Vec<u8>> {
+    if is_stdio(file_path) {
+        let mut bytes = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        return Ok(bytes);
+    }
+
+    if !file_path.exists() {
+        bail!("file {} does not exist", file_path.display());
+    }
+    Ok(std::fs::read(file_path)?)
+}
+
+/// Runs `read` on the contents of a file, memory-mapping it instead of reading it into memory
+fn with_input<T>(file_path: &Path, read: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    if is_stdio(file_path) || !file_path.exists() {
+        return read(&read_input(file_path)?);
+    }
+    let file = std::fs::File::open(file_path)
+        .with_context(|| format!("could not open {}", file_path.display()))?;
+    read(&PngRef::map(&file)?)
+}
+
+fn read_png(file_path: &Path) -> Result<Png> {
+    Png::try_from(read_input(file_path)?.as_slice())
+}
 
-    let mut data = png.as_bytes();
+/// Where a modified png is written
+#[derive(Clone, Copy)]
+enum Destination<'a> {
+    /// Atomically replace the file, keeping the original under the backup suffix if given
+    File {
+        backup: Option<&'a str>,
+    },
+    /// Edit the end of the file directly, see [`inplace`]
+    InPlace,
+    Stdout,
+}
+
+fn in_place_destination(backup: &Option<String>) -> Result<Destination<'static>> {
+    if backup.is_some() {
+        bail!("--in-place edits the file itself and can not keep a backup")
+    }
+    Ok(Destination::InPlace)
+}
+
+fn open_in_place(file_path: &Path) -> Result<std::fs::File> {
+    if is_stdio(file_path) {
+        bail!("a png read from standard input can not be edited in place")
+    }
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(file_path)
+        .with_context(|| format!("could not open {}", file_path.display()))
+}
+
+fn write_png(file_path: &Path, png: &Png, destination: Destination) -> Result<()> {
+    let data = png.as_bytes();
+    match destination {
+        Destination::Stdout => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+        Destination::InPlace => bail!("this command can not edit files in place"),
+        Destination::File { .. } if is_stdio(file_path) => {
+            bail!("a png read from standard input can only be written out with --stdout")
+        }
+        Destination::File { backup } => atomic::write(file_path, &data, backup)?,
+    }
+    Ok(())
+}
+
+fn is_stdio(file_path: &Path) -> bool {
+    file_path == Path::new(STDIO_PATH)
+}
+
+fn diff_pngs(old_file: PathBuf, new_file: PathBuf) -> Result<PngDiff> {
+    Ok(diff::diff(&read_png(&old_file)?, &read_png(&new_file)?))
+}
 
-    output.seek(std::io::SeekFrom::Start(0))?;
+fn compare_pngs(
+    old_file: PathBuf,
+    new_file: PathBuf,
+    diff_map: Option<PathBuf>,
+    max_difference: Option<u32>,
+    min_psnr: Option<f64>,
+) -> Result<CompareOutput> {
+    let (comparison, map) = compare::compare(&read_png(&old_file)?, &read_png(&new_file)?)?;
 
-    output.write_all(&mut data)?;
+    if let Some(path) = &diff_map {
+        atomic::write(path, &map.to_png(6)?.as_bytes(), None)?;
+    }
 
-    output.set_len(data.len() as u64)?;
+    let within_threshold = max_difference.is_none_or(|max| comparison.max_difference <= max)
+        && min_psnr.is_none_or(|min| comparison.psnr.is_none_or(|psnr| psnr >= min));
 
-    output.sync_all()?;
+    Ok(CompareOutput {
+        comparison,
+        diff_map,
+        within_threshold,
+    })
+}
 
-    drop(output);
+fn repair_png(
+    file_path: PathBuf,
+    options: RepairOptions,
+    output_file: PathBuf,
+) -> Result<RepairOutput> {
+    let repair = repair::repair(&read_input(&file_path)?, options)?;
+    let data = repair.png.as_bytes();
+    atomic::write(&output_file, &data, None)?;
+
+    Ok(RepairOutput {
+        file_path: output_file,
+        size: data.len(),
+        fixes: repair.fixes,
+    })
+}
+
+fn edit_trailer(
+    file_path: PathBuf,
+    extract: Option<PathBuf>,
+    strip: bool,
+    write: Option<PathBuf>,
+    destination: Destination,
+) -> Result<TrailerOutput> {
+    let bytes = read_input(&file_path)?;
+    let layout = Png::layout(&bytes)?;
+    let mut png = Png::try_from(bytes.as_slice())?;
+
+    let action = if let Some(extract) = extract {
+        if png.trailer().is_empty() {
+            bail!("there is no data after IEND to extract")
+        }
+        atomic::write(&extract, png.trailer(), None)?;
+        Some(TrailerAction::Extracted { file_path: extract })
+    } else if strip {
+        png.take_trailer();
+        write_png(&file_path, &png, destination)?;
+        Some(TrailerAction::Stripped)
+    } else if let Some(write) = write {
+        let trailer = std::fs::read(write)?;
+        let length = trailer.len();
+        png.set_trailer(trailer);
+        write_png(&file_path, &png, destination)?;
+        Some(TrailerAction::Written { length })
+    } else {
+        None
+    };
 
-    Ok(format!(
-        "wrote message to file {}",
-        output_path.to_str().unwrap()
-    ))
+    let original = &bytes[Png::trailer_offset(&layout)..];
+    Ok(TrailerOutput {
+        offset: Png::trailer_offset(&layout),
+        length: original.len(),
+        format: trailer::identify(original),
+        legacy_chunks: trailer::legacy_chunks(original)
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect(),
+        action,
+    })
 }
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use anyhow::bail;
+use serde::Serialize;
+
+use crate::image::{decode_rgba8, Image, GRAYSCALE, INDEXED};
+use crate::png::Png;
+use crate::Result;
+
+/// Pixel-level difference metrics between two images of the same dimensions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    pub width: u32,
+    pub height: u32,
+    /// Largest absolute difference of any sample
+    pub max_difference: u32,
+    /// Largest value a sample can take in the compared format
+    pub max_value: u32,
+    pub changed_pixels: usize,
+    pub total_pixels: usize,
+    pub mean_squared_error: f64,
+    /// Peak signal-to-noise ratio in dB, `None` when the images are identical
+    pub psnr: Option<f64>,
+}
+
+impl Comparison {
+    pub fn is_identical(&self) -> bool {
+        self.changed_pixels == 0
+    }
+}
+
+/// Decodes both pngs and compares their samples. Images in the same color type and bit depth
+/// are compared natively, unless they are indexed or have a tRNS chunk, in which case the samples
+/// are not the colours shown and both are converted to 8-bit RGBA first, as are images in
+/// different formats.
+pub fn compare(old: &Png, new: &Png) -> Result<(Comparison, Image)> {
+    let (old_image, new_image) = (Image::decode(old)?, Image::decode(new)?);
+    if (old_image.width, old_image.height) != (new_image.width, new_image.height) {
+        bail!(
+            "image dimensions differ: {}x{} and {}x{}",
+            old_image.width,
+            old_image.height,
+            new_image.width,
+            new_image.height
+        )
+    }
+
+    let same_format =
+        old_image.color_type == new_image.color_type && old_image.bit_depth == new_image.bit_depth;
+    let samples_are_colours = old_image.color_type != INDEXED
+        && old.chunk_by_type("tRNS").is_none()
+        && new.chunk_by_type("tRNS").is_none();
+
+    if same_format && samples_are_colours {
+        compare_images(&old_image, &new_image)
+    } else {
+        compare_images(&decode_rgba8(old)?, &decode_rgba8(new)?)
+    }
+}
+
+/// Compares two images of identical format, returning the metrics and an 8-bit grayscale
+/// difference map where each pixel is the largest channel difference scaled to 0-255
+pub fn compare_images(old: &Image, new: &Image) -> Result<(Comparison, Image)> {
+    if old.header() != new.header() {
+        bail!("images must have the same dimensions, color type and bit depth")
+    }
+
+    let max_value = (1u32 << old.bit_depth) - 1;
+    let channels = old.channels();
+    let (old_samples, new_samples) = (old.samples(), new.samples());
+
+    let mut max_difference = 0;
+    let mut changed_pixels = 0;
+    let mut squared_error = 0f64;
+    let mut map = Vec::with_capacity(old.width as usize * old.height as usize);
+
+    for (old_pixel, new_pixel) in old_samples
+        .chunks(channels)
+        .zip(new_samples.chunks(channels))
+    {
+        let pixel_difference = old_pixel
+            .iter()
+            .zip(new_pixel)
+            .map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs())
+            .inspect(|difference| squared_error += (*difference as f64).powi(2))
+            .max()
+            .unwrap_or(0);
+
+        if pixel_difference > 0 {
+            changed_pixels += 1;
+        }
+        max_difference = max_difference.max(pixel_difference);
+        map.push((pixel_difference * 255 / max_value) as u8);
+    }
+
+    let mean_squared_error = squared_error / old_samples.len().max(1) as f64;
+    let psnr = if mean_squared_error == 0.0 {
+        None
+    } else {
+        Some(10.0 * ((max_value as f64).powi(2) / mean_squared_error).log10())
+    };
+
+    let comparison = Comparison {
+        width: old.width,
+        height: old.height,
+        max_difference,
+        max_value,
+        changed_pixels,
+        total_pixels: old.width as usize * old.height as usize,
+        mean_squared_error,
+        psnr,
+    };
+
+    Ok((
+        comparison,
+        Image::new(old.width, old.height, GRAYSCALE, 8, map)?,
+    ))
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "dimensions: {}x{}", self.width, self.height)?;
+        writeln!(
+            f,
+            "max sample difference: {} of {}",
+            self.max_difference, self.max_value
+        )?;
+        writeln!(
+            f,
+            "changed pixels: {} of {} ({:.3}%)",
+            self.changed_pixels,
+            self.total_pixels,
+            100.0 * self.changed_pixels as f64 / self.total_pixels.max(1) as f64
+        )?;
+        match self.psnr {
+            Some(psnr) => write!(f, "psnr: {:.2} dB", psnr),
+            None => write!(f, "psnr: inf (identical)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_identical() {
+        let image = Image::new(2, 2, GRAYSCALE, 8, vec![1, 2, 3, 4]).unwrap();
+        let (comparison, map) = compare_images(&image, &image).unwrap();
+
+        assert!(comparison.is_identical());
+        assert_eq!(comparison.psnr, None);
+        assert_eq!(map.data, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let old = Image::new(2, 2, GRAYSCALE, 8, vec![10, 20, 30, 40]).unwrap();
+        let new = Image::new(2, 2, GRAYSCALE, 8, vec![10, 21, 30, 43]).unwrap();
+        let (comparison, map) = compare_images(&old, &new).unwrap();
+
+        assert_eq!(comparison.max_difference, 3);
+        assert_eq!(comparison.changed_pixels, 2);
+        assert_eq!(comparison.mean_squared_error, 2.5);
+        let psnr = comparison.psnr.unwrap();
+        assert!((psnr - 44.15).abs() < 0.01, "{}", psnr);
+        assert_eq!(map.data, [0, 1, 0, 3]);
+    }
+
+    #[test]
+    fn test_different_formats_compare_as_rgba() {
+        let gray = Image::new(1, 1, GRAYSCALE, 8, vec![200]).unwrap();
+        let rgb = Image::new(1, 1, crate::image::TRUECOLOR, 8, vec![200, 200, 200]).unwrap();
+        let (comparison, _) = compare(&gray.to_png(6).unwrap(), &rgb.to_png(6).unwrap()).unwrap();
+        assert!(comparison.is_identical());
+    }
+
+    fn with_chunk(mut png: Png, chunk_type: &str, data: &[u8]) -> Png {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        png.insert_chunk(1, Chunk::new(chunk_type, data.to_vec()));
+        png
+    }
+
+    #[test]
+    fn test_palettes_compare_as_colours() {
+        let indexed = |indices: Vec<u8>, palette: &[u8]| {
+            let image = Image::new(2, 1, INDEXED, 8, indices).unwrap();
+            with_chunk(image.to_png(6).unwrap(), "PLTE", palette)
+        };
+        let old = indexed(vec![0, 1], &[255, 0, 0, 0, 0, 255]);
+        let new = indexed(vec![1, 0], &[0, 0, 255, 255, 0, 0]);
+
+        let (comparison, _) = compare(&old, &new).unwrap();
+        assert!(comparison.is_identical());
+    }
+
+    #[test]
+    fn test_transparency_differs() {
+        let image = Image::new(2, 1, GRAYSCALE, 8, vec![200, 100]).unwrap();
+        let old = image.to_png(6).unwrap();
+        let new = with_chunk(image.to_png(6).unwrap(), "tRNS", &[0, 200]);
+
+        let (comparison, _) = compare(&old, &new).unwrap();
+        assert_eq!(comparison.changed_pixels, 1);
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        let small = Image::new(1, 1, GRAYSCALE, 8, vec![0]).unwrap();
+        let large = Image::new(2, 1, GRAYSCALE, 8, vec![0, 0]).unwrap();
+        assert!(compare(&small.to_png(6).unwrap(), &large.to_png(6).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::chunk_data::ChunkData;
+use crate::image::Image;
+use crate::png::Png;
+
+/// Chunk types whose changes can alter the decoded pixels
+pub const IMAGE_CHUNK_TYPES: &[&str] = &["IHDR", "PLTE", "IDAT", "tRNS"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+    Reordered,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// A difference in one chunk, identified by its type and its occurrence among chunks of that type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChunkChange {
+    pub kind: ChangeKind,
+    pub chunk_type: String,
+    pub occurrence: usize,
+    pub old_index: Option<usize>,
+    pub new_index: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldChange>,
+}
+
+/// A difference in the data after IEND
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrailerChange {
+    pub kind: ChangeKind,
+    pub old_length: usize,
+    pub new_length: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PngDiff {
+    pub changes: Vec<ChunkChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trailer: Option<TrailerChange>,
+    /// Whether both files decode to the same pixels. Only checked when image chunks differ, and
+    /// unknown if either file fails to decode.
+    pub pixels_equal: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decode_error: Option<String>,
+}
+
+impl PngDiff {
+    /// True if the files differ only in chunks that do not affect the decoded image
+    pub fn is_metadata_only(&self) -> bool {
+        self.decode_error.is_none() && self.pixels_equal.unwrap_or(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.trailer.is_none()
+    }
+}
+
+/// Chunk key used for alignment: type and occurrence among chunks of that type
+type Key = (String, usize);
+
+fn keys(png: &Png) -> Vec<Key> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    png.chunks()
+        .iter()
+        .map(|chunk| {
+            let chunk_type = chunk.chunk_type().to_string();
+            let count = seen.entry(chunk_type.clone()).or_default();
+            *count += 1;
+            (chunk_type, *count - 1)
+        })
+        .collect()
+}
+
+/// Keys of `old` that belong to a longest common subsequence of the two key sequences. Keys are
+/// unique within a file, so this is the longest increasing run of the shared keys' positions in
+/// `new`, found by patience sorting in O(n log n) time and linear space.
+fn longest_common_subsequence<'a>(
+    old: &'a [Key],
+    new_positions: &HashMap<&Key, usize>,
+) -> HashSet<&'a Key> {
+    let shared: Vec<(&Key, usize)> = old
+        .iter()
+        .filter_map(|key| new_positions.get(key).map(|position| (key, *position)))
+        .collect();
+
+    // tails[n] is the index in `shared` of the smallest position ending an increasing run of
+    // length n + 1, and previous links each entry to the one before it in its run
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; shared.len()];
+    for (index, (_, position)) in shared.iter().enumerate() {
+        let length = tails.partition_point(|&tail| shared[tail].1 < *position);
+        previous[index] = length.checked_sub(1).map(|before| tails[before]);
+        match tails.get_mut(length) {
+            Some(tail) => *tail = index,
+            None => tails.push(index),
+        }
+    }
+
+    let mut common = HashSet::new();
+    let mut next = tails.last().copied();
+    while let Some(index) = next {
+        common.insert(shared[index].0);
+        next = previous[index];
+    }
+    common
+}
+
+/// Compares the typed fields of two chunks of the same type
+pub fn field_changes(old: &Chunk, new: &Chunk) -> Vec<FieldChange> {
+    let to_fields = |chunk: &Chunk| match ChunkData::parse(chunk) {
+        Ok(Some(data)) => serde_json::to_value(data).ok(),
+        _ => None,
+    };
+
+    let (Some(serde_json::Value::Object(old)), Some(serde_json::Value::Object(new))) =
+        (to_fields(old), to_fields(new))
+    else {
+        return Vec::new();
+    };
+
+    old.iter()
+        .filter(|(field, value)| new.get(*field) != Some(value))
+        .map(|(field, value)| FieldChange {
+            field: field.clone(),
+            old: value.clone(),
+            new: new.get(field).cloned().unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Aligns the chunks of two pngs and reports how `new` differs from `old`
+pub fn diff(old: &Png, new: &Png) -> PngDiff {
+    let old_keys = keys(old);
+    let new_keys = keys(new);
+    let new_positions: HashMap<&Key, usize> = new_keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| (key, index))
+        .collect();
+    let old_key_set: HashSet<&Key> = old_keys.iter().collect();
+    let in_order = longest_common_subsequence(&old_keys, &new_positions);
+
+    let mut changes = Vec::new();
+    for (old_index, key) in old_keys.iter().enumerate() {
+        let new_index = new_positions.get(key).copied();
+        let change = |kind, fields| ChunkChange {
+            kind,
+            chunk_type: key.0.clone(),
+            occurrence: key.1,
+            old_index: Some(old_index),
+            new_index,
+            fields,
+        };
+
+        match new_index {
+            None => changes.push(change(ChangeKind::Removed, Vec::new())),
+            Some(new_index) => {
+                let (old_chunk, new_chunk) = (&old.chunks()[old_index], &new.chunks()[new_index]);
+                if old_chunk.data() != new_chunk.data() {
+                    changes.push(change(
+                        ChangeKind::Modified,
+                        field_changes(old_chunk, new_chunk),
+                    ));
+                }
+                if !in_order.contains(key) {
+                    changes.push(change(ChangeKind::Reordered, Vec::new()));
+                }
+            }
+        }
+    }
+
+    for (new_index, key) in new_keys.iter().enumerate() {
+        if !old_key_set.contains(key) {
+            changes.push(ChunkChange {
+                kind: ChangeKind::Added,
+                chunk_type: key.0.clone(),
+                occurrence: key.1,
+                old_index: None,
+                new_index: Some(new_index),
+                fields: Vec::new(),
+            });
+        }
+    }
+
+    let trailer = match (old.trailer(), new.trailer()) {
+        (old, new) if old == new => None,
+        (old, new) => Some(TrailerChange {
+            kind: match (old.is_empty(), new.is_empty()) {
+                (true, _) => ChangeKind::Added,
+                (_, true) => ChangeKind::Removed,
+                _ => ChangeKind::Modified,
+            },
+            old_length: old.len(),
+            new_length: new.len(),
+        }),
+    };
+
+    let image_changed = changes.iter().any(|change| {
+        change.kind != ChangeKind::Reordered
+            && IMAGE_CHUNK_TYPES.contains(&change.chunk_type.as_str())
+    });
+
+    let (pixels_equal, decode_error) = if image_changed {
+        match (Image::decode(old), Image::decode(new)) {
+            (Ok(old), Ok(new)) => (Some(old == new), None),
+            (Err(error), _) => (None, Some(format!("old file does not decode: {}", error))),
+            (_, Err(error)) => (None, Some(format!("new file does not decode: {}", error))),
+        }
+    } else {
+        (None, None)
+    };
+
+    PngDiff {
+        changes,
+        trailer,
+        pixels_equal,
+        decode_error,
+    }
+}
+
+impl fmt::Display for PngDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no differences");
+        }
+
+        let label = |kind: ChangeKind| match kind {
+            ChangeKind::Added => ('+', "added"),
+            ChangeKind::Removed => ('-', "removed"),
+            ChangeKind::Modified => ('~', "modified"),
+            ChangeKind::Reordered => ('>', "reordered"),
+        };
+
+        for change in &self.changes {
+            let position = |index: Option<usize>| index.map_or("-".to_string(), |i| i.to_string());
+            let (marker, kind) = label(change.kind);
+
+            writeln!(
+                f,
+                "{} {:9} [{}] #{} (index {} -> {})",
+                marker,
+                kind,
+                change.chunk_type,
+                change.occurrence,
+                position(change.old_index),
+                position(change.new_index)
+            )?;
+
+            for field in &change.fields {
+                writeln!(f, "      {}: {} -> {}", field.field, field.old, field.new)?;
+            }
+        }
+
+        if let Some(trailer) = &self.trailer {
+            let (marker, kind) = label(trailer.kind);
+            writeln!(
+                f,
+                "{} {:9} trailer after IEND ({} -> {} bytes)",
+                marker, kind, trailer.old_length, trailer.new_length
+            )?;
+        }
+
+        if let Some(error) = &self.decode_error {
+            return write!(
+                f,
+                "image data differs, pixels could not be compared: {}",
+                error
+            );
+        }
+        match self.pixels_equal {
+            Some(true) => write!(f, "image data differs but decodes to identical pixels"),
+            Some(false) => write!(f, "pixels differ"),
+            None => write!(f, "image data unchanged, only metadata differs"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn image_png(level: u32) -> Png {
+        let image = Image::new(2, 2, 0, 8, vec![1, 2, 3, 4]).unwrap();
+        let mut png = image.to_png(level).unwrap();
+        png.insert_chunk(1, chunk("gAMA", &[0, 0, 177, 143]));
+        png.insert_chunk(2, chunk("tEXt", b"Title\0a"));
+        png
+    }
+
+    fn kinds(diff: &PngDiff) -> Vec<(ChangeKind, String)> {
+        diff.changes
+            .iter()
+            .map(|c| (c.kind, c.chunk_type.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_identical() {
+        let diff = diff(&image_png(6), &image_png(6));
+        assert!(diff.is_empty());
+        assert_eq!(diff.pixels_equal, None);
+    }
+
+    #[test]
+    fn test_added_removed_modified() {
+        let old = image_png(6);
+        let mut new = image_png(6);
+        new.remove_chunk("tEXt").unwrap();
+        new.insert_chunk(1, chunk("ruSt", b"hi"));
+        new.remove_chunk("gAMA").unwrap();
+        new.insert_chunk(1, chunk("gAMA", &[0, 0, 177, 144]));
+
+        let diff = diff(&old, &new);
+        assert_eq!(
+            kinds(&diff),
+            [
+                (ChangeKind::Modified, "gAMA".to_string()),
+                (ChangeKind::Removed, "tEXt".to_string()),
+                (ChangeKind::Added, "ruSt".to_string()),
+            ]
+        );
+        assert_eq!(diff.changes[0].fields[0].field, "gamma");
+        assert!(diff.is_metadata_only());
+    }
+
+    #[test]
+    fn test_reordered() {
+        let old = image_png(6);
+        let mut new = image_png(6);
+        let text = new.remove_chunk("tEXt").unwrap();
+        new.insert_chunk(1, text);
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.changes[0].kind, ChangeKind::Reordered);
+    }
+
+    #[test]
+    fn test_many_chunks() {
+        let chunks = |moved: usize| {
+            let mut chunks: Vec<Chunk> = (0..20_000).map(|_| chunk("ruSt", &[0])).collect();
+            chunks.insert(moved, chunk("tEXt", b"Comment\0moved"));
+            Png::from_chunks(chunks)
+        };
+
+        let diff = diff(&chunks(10), &chunks(19_990));
+        assert_eq!(kinds(&diff), [(ChangeKind::Reordered, "tEXt".to_string())]);
+    }
+
+    #[test]
+    fn test_trailer() {
+        let old = image_png(6);
+        let mut new = image_png(6);
+        new.set_trailer(b"PK\x03\x04".to_vec());
+
+        let added = diff(&old, &new);
+        assert!(added.changes.is_empty());
+        assert_eq!(
+            added.trailer,
+            Some(TrailerChange {
+                kind: ChangeKind::Added,
+                old_length: 0,
+                new_length: 4,
+            })
+        );
+        assert!(!added.is_empty());
+        assert!(added.is_metadata_only());
+
+        let removed = diff(&new, &old);
+        assert_eq!(removed.trailer.unwrap().kind, ChangeKind::Removed);
+
+        let mut changed = image_png(6);
+        changed.set_trailer(b"PK\x05\x06".to_vec());
+        assert_eq!(
+            diff(&new, &changed).trailer.unwrap().kind,
+            ChangeKind::Modified
+        );
+        assert!(diff(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_recompressed_pixels_equal() {
+        let diff = diff(&image_png(0), &image_png(9));
+        assert_eq!(diff.pixels_equal, Some(true));
+        assert!(diff.is_metadata_only());
+    }
+
+    #[test]
+    fn test_corrupt_image_data() {
+        let old = image_png(6);
+        let mut new = image_png(6);
+        let idat = new.remove_chunk("IDAT").unwrap();
+        new.insert_chunk(3, chunk("IDAT", &idat.data()[..idat.data().len() / 2]));
+
+        let diff = diff(&old, &new);
+        assert_eq!(diff.pixels_equal, None);
+        assert!(diff.decode_error.as_ref().unwrap().starts_with("new file"));
+        assert!(!diff.is_metadata_only());
+    }
+}
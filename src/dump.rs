@@ -0,0 +1,250 @@
+use std::str::FromStr;
+
+use anyhow::bail;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::Chunk;
+use crate::chunk_data::ChunkData;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// Chunk data up to this length that is not text is dumped as hex, longer data as base64
+pub const MAX_HEX_LENGTH: usize = 64;
+
+/// Textual representation of a png's chunk structure that can be rebuilt into the same bytes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PngDump {
+    pub chunks: Vec<ChunkDump>,
+    /// Data after IEND
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailer: Option<DumpedData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkDump {
+    pub chunk_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    /// When present, the rebuilt chunk must have this crc
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crc: Option<u32>,
+    /// Set when `crc` does not match the data and is written as is, so corrupt files rebuild
+    /// byte for byte
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bad_crc: bool,
+    #[serde(flatten)]
+    pub data: DumpedData,
+    /// Decoded fields of known chunk types. Informational only, ignored when building.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<ChunkData>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "encoding", content = "data", rename_all = "lowercase")]
+pub enum DumpedData {
+    Utf8(String),
+    Hex(String),
+    Base64(String),
+}
+
+impl DumpedData {
+    /// Picks the most readable lossless encoding for `data`
+    pub fn encode(data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text)
+                if text
+                    .chars()
+                    .all(|c| !c.is_control() || c == '\n' || c == '\t') =>
+            {
+                DumpedData::Utf8(text.to_string())
+            }
+            _ if data.len() <= MAX_HEX_LENGTH => DumpedData::Hex(hex::encode(data)),
+            _ => DumpedData::Base64(base64::engine::general_purpose::STANDARD.encode(data)),
+        }
+    }
+
+    pub fn decode(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            DumpedData::Utf8(text) => text.as_bytes().to_vec(),
+            DumpedData::Hex(text) => hex::decode(text)?,
+            DumpedData::Base64(text) => base64::engine::general_purpose::STANDARD.decode(text)?,
+        })
+    }
+}
+
+impl ChunkDump {
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        let crc = chunk.stored_crc().unwrap_or_else(|| chunk.crc());
+        Self {
+            chunk_type: chunk.chunk_type().to_string(),
+            length: Some(chunk.length()),
+            crc: Some(crc),
+            bad_crc: crc != chunk.crc(),
+            data: DumpedData::encode(chunk.data()),
+            fields: ChunkData::parse(chunk).ok().flatten(),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let chunk_type = ChunkType::from_str(&self.chunk_type)?;
+        let chunk = Chunk::new(chunk_type.clone(), self.data.decode()?);
+
+        if let Some(length) = self.length {
+            if length != chunk.length() {
+                bail!(
+                    "chunk [{}] declares length {} but has {} bytes of data",
+                    self.chunk_type,
+                    length,
+                    chunk.length()
+                )
+            }
+        }
+
+        if let Some(crc) = self.crc {
+            if self.bad_crc {
+                return Ok(Chunk::with_crc(chunk_type, chunk.data().to_vec(), crc));
+            }
+            if crc != chunk.crc() {
+                bail!(
+                    "chunk [{}] declares crc {} but its data has crc {}; remove the crc to recompute it",
+                    self.chunk_type,
+                    crc,
+                    chunk.crc()
+                )
+            }
+        }
+
+        Ok(chunk)
+    }
+}
+
+impl PngDump {
+    pub fn from_png(png: &Png) -> Self {
+        Self {
+            chunks: png.chunks().iter().map(ChunkDump::from_chunk).collect(),
+            trailer: (!png.trailer().is_empty()).then(|| DumpedData::encode(png.trailer())),
+        }
+    }
+
+    pub fn to_png(&self) -> Result<Png> {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(ChunkDump::to_chunk)
+            .collect::<Result<Vec<Chunk>>>()?;
+        let mut png = Png::from_chunks(chunks);
+        if let Some(trailer) = &self.trailer {
+            png.set_trailer(trailer.decode()?);
+        }
+        Ok(png)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn from_yaml(text: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{ParseOptions, Serialisation};
+
+    fn testing_png() -> Png {
+        let chunk = |chunk_type: &str, data: &[u8]| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+        };
+
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", &[0x55; 100]),
+            chunk("ruSt", "secret message".as_bytes()),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(b"PK\x03\x04".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_encoding_choice() {
+        assert_eq!(
+            DumpedData::encode(b"hi"),
+            DumpedData::Utf8("hi".to_string())
+        );
+        assert_eq!(
+            DumpedData::encode(&[0, 255]),
+            DumpedData::Hex("00ff".to_string())
+        );
+        assert!(matches!(
+            DumpedData::encode(&[0; 100]),
+            DumpedData::Base64(_)
+        ));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let png = testing_png();
+        let json = PngDump::from_png(&png).to_json().unwrap();
+        let rebuilt = PngDump::from_json(&json).unwrap().to_png().unwrap();
+        assert_eq!(rebuilt.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let png = testing_png();
+        let yaml = PngDump::from_png(&png).to_yaml().unwrap();
+        let rebuilt = PngDump::from_yaml(&yaml).unwrap().to_png().unwrap();
+        assert_eq!(rebuilt.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_typed_fields() {
+        let dump = PngDump::from_png(&testing_png());
+        assert!(matches!(
+            dump.chunks[0].fields,
+            Some(ChunkData::ImageHeader(_))
+        ));
+        assert!(dump.chunks[3].fields.is_none());
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut dump = PngDump::from_png(&testing_png());
+        dump.chunks[3].data = DumpedData::Utf8("edited".to_string());
+        dump.chunks[3].length = None;
+        assert!(dump.to_png().is_err());
+
+        dump.chunks[3].crc = None;
+        assert!(dump.to_png().is_ok());
+    }
+
+    #[test]
+    fn test_bad_crc_round_trip() {
+        let mut bytes = testing_png().as_bytes();
+        let crc_offset = Png::layout(&bytes).unwrap()[3].crc_offset();
+        bytes[crc_offset] ^= 0xff;
+
+        let png = Png::parse(&bytes, ParseOptions { verify_crc: false }).unwrap();
+        let dump = PngDump::from_png(&png);
+        assert!(dump.chunks[3].bad_crc);
+        assert!(!dump.chunks[2].bad_crc);
+
+        let json = dump.to_json().unwrap();
+        let rebuilt = PngDump::from_json(&json).unwrap().to_png().unwrap();
+        assert_eq!(rebuilt.to_bytes(Serialisation::AsRead), bytes);
+    }
+}
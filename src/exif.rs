@@ -0,0 +1,825 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use anyhow::bail;
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+pub const EXIF_CHUNK_TYPE: &str = "eXIf";
+
+/// Tags in IFD0 pointing to the EXIF and GPS IFDs, and in the EXIF IFD to the interoperability IFD
+pub const EXIF_POINTER_TAG: u16 = 0x8769;
+pub const GPS_POINTER_TAG: u16 = 0x8825;
+pub const INTEROPERABILITY_POINTER_TAG: u16 = 0xa005;
+
+const TIFF_HEADER_BYTES: usize = 8;
+const ENTRY_BYTES: usize = 12;
+/// Field type of offsets to other IFDs, used by some writers instead of long
+const IFD_FIELD_TYPE: u16 = 13;
+
+/// Names of common tags, by the IFD they appear in
+const TAG_NAMES: &[(Directory, u16, &str)] = &[
+    (Directory::Ifd0, 0x010e, "ImageDescription"),
+    (Directory::Ifd0, 0x010f, "Make"),
+    (Directory::Ifd0, 0x0110, "Model"),
+    (Directory::Ifd0, 0x0112, "Orientation"),
+    (Directory::Ifd0, 0x011a, "XResolution"),
+    (Directory::Ifd0, 0x011b, "YResolution"),
+    (Directory::Ifd0, 0x0128, "ResolutionUnit"),
+    (Directory::Ifd0, 0x0131, "Software"),
+    (Directory::Ifd0, 0x0132, "DateTime"),
+    (Directory::Ifd0, 0x013b, "Artist"),
+    (Directory::Ifd0, 0x8298, "Copyright"),
+    (Directory::Exif, 0x829a, "ExposureTime"),
+    (Directory::Exif, 0x829d, "FNumber"),
+    (Directory::Exif, 0x8822, "ExposureProgram"),
+    (Directory::Exif, 0x8827, "ISOSpeedRatings"),
+    (Directory::Exif, 0x9000, "ExifVersion"),
+    (Directory::Exif, 0x9003, "DateTimeOriginal"),
+    (Directory::Exif, 0x9004, "DateTimeDigitized"),
+    (Directory::Exif, 0x9201, "ShutterSpeedValue"),
+    (Directory::Exif, 0x9202, "ApertureValue"),
+    (Directory::Exif, 0x9204, "ExposureBiasValue"),
+    (Directory::Exif, 0x9207, "MeteringMode"),
+    (Directory::Exif, 0x9209, "Flash"),
+    (Directory::Exif, 0x920a, "FocalLength"),
+    (Directory::Exif, 0x927c, "MakerNote"),
+    (Directory::Exif, 0x9286, "UserComment"),
+    (Directory::Exif, 0xa001, "ColorSpace"),
+    (Directory::Exif, 0xa002, "PixelXDimension"),
+    (Directory::Exif, 0xa003, "PixelYDimension"),
+    (Directory::Exif, 0xa402, "ExposureMode"),
+    (Directory::Exif, 0xa403, "WhiteBalance"),
+    (Directory::Exif, 0xa405, "FocalLengthIn35mmFilm"),
+    (Directory::Exif, 0xa430, "CameraOwnerName"),
+    (Directory::Exif, 0xa431, "BodySerialNumber"),
+    (Directory::Exif, 0xa433, "LensMake"),
+    (Directory::Exif, 0xa434, "LensModel"),
+    (Directory::Gps, 0x0000, "GPSVersionID"),
+    (Directory::Gps, 0x0001, "GPSLatitudeRef"),
+    (Directory::Gps, 0x0002, "GPSLatitude"),
+    (Directory::Gps, 0x0003, "GPSLongitudeRef"),
+    (Directory::Gps, 0x0004, "GPSLongitude"),
+    (Directory::Gps, 0x0005, "GPSAltitudeRef"),
+    (Directory::Gps, 0x0006, "GPSAltitude"),
+    (Directory::Gps, 0x0007, "GPSTimeStamp"),
+    (Directory::Gps, 0x0010, "GPSImgDirectionRef"),
+    (Directory::Gps, 0x0011, "GPSImgDirection"),
+    (Directory::Gps, 0x0012, "GPSMapDatum"),
+    (Directory::Gps, 0x001d, "GPSDateStamp"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteOrder {
+    /// "II", least significant byte first
+    LittleEndian,
+    /// "MM", most significant byte first
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+}
+
+/// The image file directory an entry was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Directory {
+    Ifd0,
+    Exif,
+    Gps,
+}
+
+impl fmt::Display for Directory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Directory::Ifd0 => "ifd0",
+            Directory::Exif => "exif",
+            Directory::Gps => "gps",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A tag value, decoded for the common TIFF field types. Other types, and text that is not a
+/// single NUL terminated UTF-8 string, keep their raw bytes in the byte order of the file. Types
+/// of unknown size keep the 4 bytes of their entry's value field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Value {
+    Byte(Vec<u8>),
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Rational(Vec<[u32; 2]>),
+    Undefined(Vec<u8>),
+    SignedLong(Vec<i32>),
+    SignedRational(Vec<[i32; 2]>),
+    Other {
+        field_type: u16,
+        count: u32,
+        bytes: Vec<u8>,
+    },
+}
+
+impl Value {
+    /// Size in bytes of one value of a TIFF field type
+    fn type_size(field_type: u16) -> Option<usize> {
+        match field_type {
+            1 | 2 | 6 | 7 => Some(1),
+            3 | 8 => Some(2),
+            4 | 9 | 11 | 13 => Some(4),
+            5 | 10 | 12 => Some(8),
+            _ => None,
+        }
+    }
+
+    fn parse(field_type: u16, count: u32, bytes: &[u8], order: ByteOrder) -> Value {
+        let u32s = || bytes.chunks(4).map(|word| order.u32(word));
+        match field_type {
+            1 => Value::Byte(bytes.to_vec()),
+            // only text with a single terminating NUL is decoded, so that encoding it again gives
+            // back the same bytes
+            2 if bytes.iter().position(|byte| *byte == 0)
+                == Some(bytes.len().saturating_sub(1)) =>
+            {
+                match std::str::from_utf8(&bytes[..bytes.len() - 1]) {
+                    Ok(text) => Value::Ascii(text.to_string()),
+                    Err(_) => Value::Other {
+                        field_type,
+                        count,
+                        bytes: bytes.to_vec(),
+                    },
+                }
+            }
+            3 => Value::Short(bytes.chunks(2).map(|word| order.u16(word)).collect()),
+            4 => Value::Long(u32s().collect()),
+            5 => Value::Rational(
+                u32s()
+                    .collect::<Vec<_>>()
+                    .chunks(2)
+                    .map(|pair| [pair[0], pair[1]])
+                    .collect(),
+            ),
+            7 => Value::Undefined(bytes.to_vec()),
+            9 => Value::SignedLong(u32s().map(|value| value as i32).collect()),
+            10 => Value::SignedRational(
+                u32s()
+                    .collect::<Vec<_>>()
+                    .chunks(2)
+                    .map(|pair| [pair[0] as i32, pair[1] as i32])
+                    .collect(),
+            ),
+            _ => Value::Other {
+                field_type,
+                count,
+                bytes: bytes.to_vec(),
+            },
+        }
+    }
+
+    /// Field type, count and bytes to store the value with
+    fn encode(&self, order: ByteOrder) -> (u16, u32, Vec<u8>) {
+        let u32s = |values: &mut dyn Iterator<Item = u32>| -> Vec<u8> {
+            values.flat_map(|value| order.u32_bytes(value)).collect()
+        };
+        match self {
+            Value::Byte(bytes) => (1, bytes.len() as u32, bytes.clone()),
+            Value::Ascii(text) => {
+                let mut bytes = text.as_bytes().to_vec();
+                bytes.push(0);
+                (2, bytes.len() as u32, bytes)
+            }
+            Value::Short(values) => (
+                3,
+                values.len() as u32,
+                values
+                    .iter()
+                    .flat_map(|value| order.u16_bytes(*value))
+                    .collect(),
+            ),
+            Value::Long(values) => (4, values.len() as u32, u32s(&mut values.iter().copied())),
+            Value::Rational(values) => (
+                5,
+                values.len() as u32,
+                u32s(&mut values.iter().flatten().copied()),
+            ),
+            Value::Undefined(bytes) => (7, bytes.len() as u32, bytes.clone()),
+            Value::SignedLong(values) => (
+                9,
+                values.len() as u32,
+                u32s(&mut values.iter().map(|value| *value as u32)),
+            ),
+            Value::SignedRational(values) => (
+                10,
+                values.len() as u32,
+                u32s(&mut values.iter().flatten().map(|value| *value as u32)),
+            ),
+            Value::Other {
+                field_type,
+                count,
+                bytes,
+            } => (*field_type, *count, bytes.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: fmt::Display>(values: impl Iterator<Item = T>) -> String {
+            values
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        match self {
+            Value::Ascii(text) => write!(f, "{}", text),
+            Value::Byte(bytes) => write!(f, "{}", list(bytes.iter())),
+            Value::Short(values) => write!(f, "{}", list(values.iter())),
+            Value::Long(values) => write!(f, "{}", list(values.iter())),
+            Value::SignedLong(values) => write!(f, "{}", list(values.iter())),
+            Value::Rational(values) => write!(
+                f,
+                "{}",
+                list(values.iter().map(|[num, den]| format!("{}/{}", num, den)))
+            ),
+            Value::SignedRational(values) => write!(
+                f,
+                "{}",
+                list(values.iter().map(|[num, den]| format!("{}/{}", num, den)))
+            ),
+            Value::Undefined(bytes) | Value::Other { bytes, .. } => {
+                if !bytes.is_empty() && bytes.iter().all(|byte| byte.is_ascii_graphic()) {
+                    write!(f, "{}", String::from_utf8_lossy(bytes))
+                } else {
+                    write!(f, "{} bytes", bytes.len())
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub tag: u16,
+    pub value: Value,
+}
+
+impl Entry {
+    /// Name of a common tag, if known
+    pub fn name(&self, directory: Directory) -> Option<&'static str> {
+        TAG_NAMES
+            .iter()
+            .find(|(known, tag, _)| *known == directory && *tag == self.tag)
+            .map(|(_, _, name)| *name)
+    }
+}
+
+/// EXIF data as stored in an eXIf chunk: a TIFF structure with IFD0 and optional EXIF and GPS
+/// IFDs. The thumbnail IFD and the interoperability IFD are not kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Exif {
+    pub byte_order: ByteOrder,
+    pub ifd0: Vec<Entry>,
+    pub exif: Option<Vec<Entry>>,
+    pub gps: Option<Vec<Entry>>,
+}
+
+impl Exif {
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < TIFF_HEADER_BYTES {
+            bail!("eXIf is shorter than a TIFF header")
+        }
+        let byte_order = match &data[0..4] {
+            b"II*\0" => ByteOrder::LittleEndian,
+            b"MM\0*" => ByteOrder::BigEndian,
+            _ => bail!("eXIf does not start with a TIFF header"),
+        };
+
+        let mut ifd0 = read_ifd(data, byte_order.u32(&data[4..8]) as usize, byte_order)?;
+        let pointer = |entries: &mut Vec<Entry>, tag: u16| -> Result<Option<Vec<Entry>>> {
+            let Some(position) = entries.iter().position(|entry| entry.tag == tag) else {
+                return Ok(None);
+            };
+            let offset = match entries.remove(position).value {
+                Value::Long(offsets) if offsets.len() == 1 => offsets[0],
+                Value::Other {
+                    field_type: IFD_FIELD_TYPE,
+                    count: 1,
+                    bytes,
+                } => byte_order.u32(&bytes),
+                _ => bail!("IFD pointer tag {:#06x} is not a single offset", tag),
+            };
+            read_ifd(data, offset as usize, byte_order).map(Some)
+        };
+
+        let exif = pointer(&mut ifd0, EXIF_POINTER_TAG)?.map(|mut exif| {
+            exif.retain(|entry| entry.tag != INTEROPERABILITY_POINTER_TAG);
+            exif
+        });
+        let gps = pointer(&mut ifd0, GPS_POINTER_TAG)?;
+
+        Ok(Self {
+            byte_order,
+            ifd0,
+            exif,
+            gps,
+        })
+    }
+
+    /// Serialises to a new TIFF structure in the original byte order, IFD0 first, then the EXIF
+    /// and GPS IFDs. Values move, so offsets stored inside them, as in most MakerNotes, no longer
+    /// hold; [`remove_gps`] edits existing data without moving anything.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let order = self.byte_order;
+        let pointer = |tag: u16, offset: usize| Entry {
+            tag,
+            value: Value::Long(vec![offset as u32]),
+        };
+
+        // pointer values do not change the size of IFD0, so placeholders give the layout
+        let mut ifd0 = self.ifd0.clone();
+        if self.exif.is_some() {
+            ifd0.push(pointer(EXIF_POINTER_TAG, 0));
+        }
+        if self.gps.is_some() {
+            ifd0.push(pointer(GPS_POINTER_TAG, 0));
+        }
+
+        let exif_offset = TIFF_HEADER_BYTES + ifd_size(&ifd0, order);
+        let gps_offset = exif_offset + self.exif.as_ref().map_or(0, |exif| ifd_size(exif, order));
+        for entry in &mut ifd0 {
+            match entry.tag {
+                EXIF_POINTER_TAG => *entry = pointer(EXIF_POINTER_TAG, exif_offset),
+                GPS_POINTER_TAG => *entry = pointer(GPS_POINTER_TAG, gps_offset),
+                _ => {}
+            }
+        }
+
+        let mut bytes = match order {
+            ByteOrder::LittleEndian => b"II*\0".to_vec(),
+            ByteOrder::BigEndian => b"MM\0*".to_vec(),
+        };
+        bytes.extend(order.u32_bytes(TIFF_HEADER_BYTES as u32));
+        write_ifd(&mut bytes, &ifd0, order);
+        if let Some(exif) = &self.exif {
+            write_ifd(&mut bytes, exif, order);
+        }
+        if let Some(gps) = &self.gps {
+            write_ifd(&mut bytes, gps, order);
+        }
+        bytes
+    }
+
+    /// Every entry with the IFD it belongs to
+    pub fn entries(&self) -> impl Iterator<Item = (Directory, &Entry)> {
+        let ifds = [
+            (Directory::Ifd0, Some(&self.ifd0)),
+            (Directory::Exif, self.exif.as_ref()),
+            (Directory::Gps, self.gps.as_ref()),
+        ];
+        ifds.into_iter().flat_map(|(directory, entries)| {
+            entries
+                .into_iter()
+                .flatten()
+                .map(move |entry| (directory, entry))
+        })
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        Ok(Chunk::new(
+            ChunkType::from_str(EXIF_CHUNK_TYPE)?,
+            self.to_bytes(),
+        ))
+    }
+}
+
+/// An IFD entry as stored
+struct RawEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    /// Offset of the entry itself
+    position: usize,
+    /// Location of the value, inside the entry when it fits in 4 bytes. None for types of
+    /// unknown size.
+    value: Option<Range<usize>>,
+}
+
+/// Reads the entries of the IFD at `offset`, checking that their values lie within `data`
+fn raw_entries(data: &[u8], offset: usize, order: ByteOrder) -> Result<Vec<RawEntry>> {
+    if offset < TIFF_HEADER_BYTES || offset + 2 > data.len() {
+        bail!("IFD offset {} is outside the EXIF data", offset)
+    }
+    let count = order.u16(&data[offset..]) as usize;
+    let entries_end = offset + 2 + count * ENTRY_BYTES;
+    if entries_end > data.len() {
+        bail!("IFD at offset {} is truncated", offset)
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for position in (offset + 2..entries_end).step_by(ENTRY_BYTES) {
+        let entry = &data[position..position + ENTRY_BYTES];
+        let tag = order.u16(&entry[0..2]);
+        let field_type = order.u16(&entry[2..4]);
+        let count = order.u32(&entry[4..8]);
+
+        let value = match Value::type_size(field_type) {
+            None => None,
+            Some(size) => {
+                let length = size as u64 * count as u64;
+                let start = if length <= 4 {
+                    position as u64 + 8
+                } else {
+                    order.u32(&entry[8..12]) as u64
+                };
+                if start + length > data.len() as u64 {
+                    bail!("value of tag {:#06x} is outside the EXIF data", tag)
+                }
+                Some(start as usize..(start + length) as usize)
+            }
+        };
+        entries.push(RawEntry {
+            tag,
+            field_type,
+            count,
+            position,
+            value,
+        });
+    }
+    Ok(entries)
+}
+
+fn read_ifd(data: &[u8], offset: usize, order: ByteOrder) -> Result<Vec<Entry>> {
+    Ok(raw_entries(data, offset, order)?
+        .into_iter()
+        .map(|entry| {
+            let value_field = entry.position + 8..entry.position + ENTRY_BYTES;
+            let bytes = &data[entry.value.unwrap_or(value_field)];
+            Entry {
+                tag: entry.tag,
+                value: Value::parse(entry.field_type, entry.count, bytes, order),
+            }
+        })
+        .collect())
+}
+
+/// Size of an IFD with its values, each value padded to an even length
+fn ifd_size(entries: &[Entry], order: ByteOrder) -> usize {
+    let values: usize = entries
+        .iter()
+        .map(|entry| entry.value.encode(order).2.len())
+        .filter(|length| *length > 4)
+        .map(|length| length + length % 2)
+        .sum();
+    2 + entries.len() * ENTRY_BYTES + 4 + values
+}
+
+/// Appends an IFD with entries sorted by tag, followed by the values that do not fit inline
+fn write_ifd(bytes: &mut Vec<u8>, entries: &[Entry], order: ByteOrder) {
+    let mut entries: Vec<&Entry> = entries.iter().collect();
+    entries.sort_by_key(|entry| entry.tag);
+
+    let start = bytes.len();
+    let mut value_offset = start + 2 + entries.len() * ENTRY_BYTES + 4;
+    let mut values = Vec::new();
+
+    bytes.extend(order.u16_bytes(entries.len() as u16));
+    for entry in entries {
+        let (field_type, count, mut data) = entry.value.encode(order);
+        bytes.extend(order.u16_bytes(entry.tag));
+        bytes.extend(order.u16_bytes(field_type));
+        bytes.extend(order.u32_bytes(count));
+        if data.len() <= 4 {
+            data.resize(4, 0);
+            bytes.extend(data);
+        } else {
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+            bytes.extend(order.u32_bytes(value_offset as u32));
+            value_offset += data.len();
+            values.extend(data);
+        }
+    }
+    // no further IFD follows
+    bytes.extend(order.u32_bytes(0));
+    bytes.extend(values);
+}
+
+/// Removes the GPS IFD from the TIFF data of an eXIf chunk by deleting its pointer from IFD0 and
+/// zeroing the GPS IFD and its values. Every other byte stays where it was, so the thumbnail IFD,
+/// offsets inside a MakerNote and entries of unknown types are untouched. Returns the new data
+/// and the number of GPS fields, or None if there is no GPS IFD.
+pub fn remove_gps(data: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+    let byte_order = Exif::from_bytes(data)?.byte_order;
+    let ifd0_offset = byte_order.u32(&data[4..8]) as usize;
+    let ifd0 = raw_entries(data, ifd0_offset, byte_order)?;
+    let Some(pointer) = ifd0.iter().find(|entry| entry.tag == GPS_POINTER_TAG) else {
+        return Ok(None);
+    };
+
+    let mut bytes = data.to_vec();
+    let gps_offset = byte_order.u32(&data[pointer.position + 8..pointer.position + 12]) as usize;
+    let gps = raw_entries(data, gps_offset, byte_order)?;
+    let gps_end = (gps_offset + 2 + gps.len() * ENTRY_BYTES + 4).min(data.len());
+    bytes[gps_offset..gps_end].fill(0);
+    for value in gps.iter().filter_map(|entry| entry.value.clone()) {
+        bytes[value].fill(0);
+    }
+
+    // shift the entries after the pointer and the next IFD offset over it
+    let ifd0_end = ifd0_offset + 2 + ifd0.len() * ENTRY_BYTES + 4;
+    if ifd0_end > data.len() {
+        bail!("IFD0 has no next IFD offset")
+    }
+    bytes.copy_within(pointer.position + ENTRY_BYTES..ifd0_end, pointer.position);
+    bytes[ifd0_end - ENTRY_BYTES..ifd0_end].fill(0);
+    bytes[ifd0_offset..ifd0_offset + 2]
+        .copy_from_slice(&byte_order.u16_bytes(ifd0.len() as u16 - 1));
+
+    Ok(Some((bytes, gps.len())))
+}
+
+/// Parses the eXIf chunk of `png`, if it has one
+pub fn read(png: &Png) -> Result<Option<Exif>> {
+    png.chunk_by_type(EXIF_CHUNK_TYPE)
+        .map(|chunk| Exif::from_bytes(chunk.data()))
+        .transpose()
+}
+
+/// Replaces any eXIf chunk of `png` with `exif`, placed before the first IDAT as PNG requires
+pub fn write(png: &mut Png, exif: &Exif) -> Result<()> {
+    remove(png);
+    let index = png
+        .chunks()
+        .iter()
+        .position(|chunk| chunk.chunk_type().to_string() == "IDAT")
+        .unwrap_or(png.chunks().len().saturating_sub(1));
+    png.insert_chunk(index, exif.to_chunk()?);
+    Ok(())
+}
+
+/// Removes the GPS IFD from the eXIf chunk of `png` with [`remove_gps`], keeping the chunk in
+/// place. Returns the number of GPS fields removed, or None if there were none.
+pub fn remove_gps_fields(png: &mut Png) -> Result<Option<usize>> {
+    let Some(chunk) = png.chunk_by_type(EXIF_CHUNK_TYPE) else {
+        return Ok(None);
+    };
+    let Some((data, fields)) = remove_gps(chunk.data())? else {
+        return Ok(None);
+    };
+
+    let index = png
+        .chunks()
+        .iter()
+        .position(|chunk| chunk.chunk_type().to_string() == EXIF_CHUNK_TYPE)
+        .unwrap();
+    png.remove_chunk(EXIF_CHUNK_TYPE)?;
+    png.insert_chunk(
+        index,
+        Chunk::new(ChunkType::from_str(EXIF_CHUNK_TYPE)?, data),
+    );
+    Ok(Some(fields))
+}
+
+/// Removes every eXIf chunk, returning how many there were
+pub fn remove(png: &mut Png) -> usize {
+    png.remove_chunks_where(|chunk| chunk.chunk_type().to_string() == EXIF_CHUNK_TYPE)
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascii(tag: u16, text: &str) -> Entry {
+        Entry {
+            tag,
+            value: Value::Ascii(text.to_string()),
+        }
+    }
+
+    fn testing_exif(byte_order: ByteOrder) -> Exif {
+        Exif {
+            byte_order,
+            ifd0: vec![
+                ascii(0x010f, "Ferris Optics"),
+                ascii(0x0110, "F1"),
+                Entry {
+                    tag: 0x0112,
+                    value: Value::Short(vec![1]),
+                },
+            ],
+            exif: Some(vec![
+                Entry {
+                    tag: 0x829a,
+                    value: Value::Rational(vec![[1, 250]]),
+                },
+                Entry {
+                    tag: 0x9000,
+                    value: Value::Undefined(b"0232".to_vec()),
+                },
+            ]),
+            gps: Some(vec![
+                ascii(0x0001, "N"),
+                Entry {
+                    tag: 0x0002,
+                    value: Value::Rational(vec![[52, 1], [31, 1], [1234, 100]]),
+                },
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_both_byte_orders() {
+        for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let exif = testing_exif(byte_order);
+            let bytes = exif.to_bytes();
+            assert_eq!(Exif::from_bytes(&bytes).unwrap(), exif);
+        }
+    }
+
+    #[test]
+    fn test_parse_big_endian() {
+        // IFD0 with Make pointing outside the entry and an inline Orientation
+        let mut bytes = b"MM\0*\0\0\0\x08\0\x02".to_vec();
+        bytes.extend([0x01, 0x0f, 0, 2, 0, 0, 0, 6, 0, 0, 0, 38]);
+        bytes.extend([0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        bytes.extend([0, 0, 0, 0]);
+        bytes.extend(b"Canon\0");
+
+        let exif = Exif::from_bytes(&bytes).unwrap();
+        assert_eq!(exif.byte_order, ByteOrder::BigEndian);
+        assert_eq!(exif.ifd0[0].value, Value::Ascii("Canon".to_string()));
+        assert_eq!(exif.ifd0[0].name(Directory::Ifd0), Some("Make"));
+        assert_eq!(exif.ifd0[1].value, Value::Short(vec![6]));
+        assert!(exif.gps.is_none());
+
+        assert!(Exif::from_bytes(&bytes[..30]).is_err());
+    }
+
+    /// EXIF data with values a rewrite would lose: multi-string text, an unknown field type, a
+    /// GPS pointer of type IFD and a thumbnail IFD after IFD0
+    fn testing_raw_exif() -> Vec<u8> {
+        let mut exif = testing_exif(ByteOrder::LittleEndian);
+        exif.ifd0.push(Entry {
+            tag: 0x010d,
+            value: Value::Other {
+                field_type: 2,
+                count: 13,
+                bytes: b"first\0second\0".to_vec(),
+            },
+        });
+        exif.ifd0.push(Entry {
+            tag: 0xc000,
+            value: Value::Other {
+                field_type: 99,
+                count: 1,
+                bytes: vec![1, 2, 3, 4],
+            },
+        });
+        let mut bytes = exif.to_bytes();
+        let order = ByteOrder::LittleEndian;
+
+        let ifd0 = raw_entries(&bytes, TIFF_HEADER_BYTES, order).unwrap();
+        let pointer = ifd0
+            .iter()
+            .find(|entry| entry.tag == GPS_POINTER_TAG)
+            .unwrap();
+        bytes[pointer.position + 2..pointer.position + 4]
+            .copy_from_slice(&order.u16_bytes(IFD_FIELD_TYPE));
+
+        let next_ifd = TIFF_HEADER_BYTES + 2 + ifd0.len() * ENTRY_BYTES;
+        let ifd1 = bytes.len() as u32;
+        bytes[next_ifd..next_ifd + 4].copy_from_slice(&order.u32_bytes(ifd1));
+        bytes.extend([1, 0, 0x03, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn test_raw_values_kept() {
+        let bytes = testing_raw_exif();
+        let exif = Exif::from_bytes(&bytes).unwrap();
+
+        assert_eq!(exif.gps.as_ref().unwrap().len(), 2);
+        let value = |tag: u16| {
+            &exif
+                .ifd0
+                .iter()
+                .find(|entry| entry.tag == tag)
+                .unwrap()
+                .value
+        };
+        assert_eq!(
+            value(0x010d),
+            &Value::Other {
+                field_type: 2,
+                count: 13,
+                bytes: b"first\0second\0".to_vec()
+            }
+        );
+        assert!(matches!(value(0xc000), Value::Other { field_type: 99, .. }));
+    }
+
+    #[test]
+    fn test_remove_gps_in_place() {
+        let order = ByteOrder::LittleEndian;
+        let bytes = testing_raw_exif();
+        let (removed, fields) = remove_gps(&bytes).unwrap().unwrap();
+        assert_eq!(fields, 2);
+        assert_eq!(removed.len(), bytes.len());
+
+        // only the entry table of IFD0 and the GPS IFD with its values may change
+        let ifd0 = raw_entries(&bytes, TIFF_HEADER_BYTES, order).unwrap();
+        let pointer = ifd0
+            .iter()
+            .find(|entry| entry.tag == GPS_POINTER_TAG)
+            .unwrap();
+        let gps_offset = order.u32(&bytes[pointer.position + 8..]) as usize;
+        let gps = raw_entries(&bytes, gps_offset, order).unwrap();
+        let mut changed = vec![
+            TIFF_HEADER_BYTES..TIFF_HEADER_BYTES + 2,
+            pointer.position..TIFF_HEADER_BYTES + 2 + ifd0.len() * ENTRY_BYTES + 4,
+            gps_offset..gps_offset + 2 + gps.len() * ENTRY_BYTES + 4,
+        ];
+        changed.extend(gps.iter().filter_map(|entry| entry.value.clone()));
+        for (offset, (old, new)) in bytes.iter().zip(&removed).enumerate() {
+            if !changed.iter().any(|range| range.contains(&offset)) {
+                assert_eq!(old, new, "byte {} changed", offset);
+            }
+        }
+        for range in &changed[2..] {
+            assert!(removed[range.clone()].iter().all(|byte| *byte == 0));
+        }
+
+        let before = Exif::from_bytes(&bytes).unwrap();
+        let after = Exif::from_bytes(&removed).unwrap();
+        assert_eq!(after.gps, None);
+        assert_eq!((after.ifd0, after.exif), (before.ifd0, before.exif));
+
+        // the thumbnail IFD is still linked from IFD0
+        let next_ifd = TIFF_HEADER_BYTES + 2 + (ifd0.len() - 1) * ENTRY_BYTES;
+        assert_eq!(
+            order.u32(&removed[next_ifd..]),
+            order.u32(&bytes[next_ifd + ENTRY_BYTES..])
+        );
+
+        assert_eq!(remove_gps(&removed).unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_before_image_data() {
+        let mut png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let mut exif = testing_exif(ByteOrder::LittleEndian);
+        write(&mut png, &exif).unwrap();
+
+        exif.gps = None;
+        write(&mut png, &exif).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        let position = |chunk_type: &str| types.iter().position(|t| t == chunk_type);
+        assert_eq!(types.iter().filter(|t| *t == "eXIf").count(), 1);
+        assert!(position("eXIf") < position("IDAT"));
+        assert_eq!(read(&png).unwrap(), Some(exif));
+    }
+}
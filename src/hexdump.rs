@@ -0,0 +1,246 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::png::{ChunkLayout, Png};
+use crate::Result;
+
+pub const BYTES_PER_LINE: usize = 16;
+
+/// A labelled range of a png file
+#[derive(Debug, Serialize)]
+pub struct Region {
+    pub offset: usize,
+    pub length: usize,
+    pub label: String,
+    #[serde(skip)]
+    pub bytes: Vec<u8>,
+}
+
+/// Selects which chunks are annotated. `index` counts chunks of `chunk_type` when it is set,
+/// otherwise all chunks.
+#[derive(Debug, Default, Clone)]
+pub struct ChunkFilter {
+    pub chunk_type: Option<String>,
+    pub index: Option<usize>,
+}
+
+impl ChunkFilter {
+    fn select<'a>(&self, layout: &'a [ChunkLayout]) -> Vec<&'a ChunkLayout> {
+        layout
+            .iter()
+            .filter(|chunk| match &self.chunk_type {
+                Some(chunk_type) => chunk.chunk_type.to_string() == *chunk_type,
+                None => true,
+            })
+            .enumerate()
+            .filter(|(index, _)| self.index.is_none_or(|wanted| wanted == *index))
+            .map(|(_, chunk)| chunk)
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.chunk_type.is_none() && self.index.is_none()
+    }
+}
+
+/// Splits a png file into labelled regions: the signature, then the length, type, data and crc of
+/// each chunk. If a chunk cannot be read, everything from it to the end of the file becomes a
+/// single unparseable region.
+pub fn regions(bytes: &[u8], filter: &ChunkFilter) -> Result<Vec<Region>> {
+    let (layout, error) = Png::partial_layout(bytes);
+    let region = |offset: usize, length: usize, label: String| Region {
+        offset,
+        length,
+        label,
+        bytes: bytes[offset..offset + length].to_vec(),
+    };
+
+    let mut regions = Vec::new();
+    if filter.is_empty() && bytes.len() >= Png::STANDARD_HEADER_LENGTH {
+        let valid = &bytes[..Png::STANDARD_HEADER_LENGTH] == Png::STANDARD_HEADER;
+        regions.push(region(
+            0,
+            Png::STANDARD_HEADER_LENGTH,
+            if valid {
+                "signature"
+            } else {
+                "signature (invalid)"
+            }
+            .to_string(),
+        ));
+    }
+
+    for chunk in filter.select(&layout) {
+        let name = format!("chunk {} [{}]", chunk.index, chunk.chunk_type);
+        let computed_crc = chunk.computed_crc(bytes);
+
+        regions.push(region(
+            chunk.offset,
+            4,
+            format!("{} length = {}", name, chunk.length),
+        ));
+        regions.push(region(chunk.offset + 4, 4, format!("{} type", name)));
+        regions.push(region(
+            chunk.data_offset(),
+            chunk.length,
+            format!("{} data ({} bytes)", name, chunk.length),
+        ));
+        regions.push(region(
+            chunk.crc_offset(),
+            4,
+            format!(
+                "{} crc = {:08x} ({})",
+                name,
+                chunk.stored_crc,
+                if computed_crc == chunk.stored_crc {
+                    "ok".to_string()
+                } else {
+                    format!("BAD, computed {:08x}", computed_crc)
+                }
+            ),
+        ));
+    }
+
+    let trailer_offset = if bytes.len() < Png::STANDARD_HEADER_LENGTH {
+        0
+    } else {
+        Png::trailer_offset(&layout)
+    };
+    if filter.is_empty() && trailer_offset < bytes.len() {
+        let length = bytes.len() - trailer_offset;
+        let label = match &error {
+            Some(error) => format!("unparseable ({} bytes): {}", length, error),
+            None => format!("trailer after IEND ({} bytes)", length),
+        };
+        regions.push(region(trailer_offset, length, label));
+    }
+
+    Ok(regions)
+}
+
+/// Formats `bytes` like `xxd`, with offsets starting at `offset`
+pub fn hex_lines(bytes: &[u8], offset: usize) -> Vec<String> {
+    bytes
+        .chunks(BYTES_PER_LINE)
+        .enumerate()
+        .map(|(line, bytes)| {
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|byte| {
+                    if byte.is_ascii_graphic() || *byte == b' ' {
+                        *byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!(
+                "{:08x}  {:<width$}  |{}|",
+                offset + line * BYTES_PER_LINE,
+                hex.join(" "),
+                ascii,
+                width = BYTES_PER_LINE * 3 - 1
+            )
+        })
+        .collect()
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}  ; {}", self.offset, self.label)?;
+        hex_lines(&self.bytes, self.offset)
+            .iter()
+            .try_for_each(|line| write!(f, "\n{}", line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunk = |chunk_type: &str, data: &[u8]| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+        };
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", b"first"),
+            chunk("IDAT", b"second"),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_regions_cover_file() {
+        let bytes = testing_bytes();
+        let regions = regions(&bytes, &ChunkFilter::default()).unwrap();
+
+        assert_eq!(regions.len(), 1 + 4 * 4);
+        let mut offset = 0;
+        for region in &regions {
+            assert_eq!(region.offset, offset);
+            offset += region.length;
+        }
+        assert_eq!(offset, bytes.len());
+    }
+
+    #[test]
+    fn test_trailer_region() {
+        let mut bytes = testing_bytes();
+        bytes.extend_from_slice(b"appended");
+        let regions = regions(&bytes, &ChunkFilter::default()).unwrap();
+
+        let trailer = regions.last().unwrap();
+        assert_eq!(trailer.bytes, b"appended");
+        assert_eq!(trailer.offset + trailer.length, bytes.len());
+    }
+
+    #[test]
+    fn test_unparseable_region() {
+        let bytes = testing_bytes();
+        let truncated = &bytes[..bytes.len() - 16];
+        let regions = regions(truncated, &ChunkFilter::default()).unwrap();
+
+        assert_eq!(regions.len(), 1 + 2 * 4 + 1);
+        let unparseable = regions.last().unwrap();
+        assert!(unparseable.label.starts_with("unparseable"));
+        assert_eq!(unparseable.offset + unparseable.length, truncated.len());
+
+        let regions = super::regions(b"not a png", &ChunkFilter::default()).unwrap();
+        assert_eq!(regions[0].label, "signature (invalid)");
+        assert_eq!(regions[1].offset, Png::STANDARD_HEADER_LENGTH);
+        assert!(regions[1].label.starts_with("unparseable"));
+
+        let regions = super::regions(b"png", &ChunkFilter::default()).unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].bytes, b"png");
+    }
+
+    #[test]
+    fn test_filter_by_type_and_index() {
+        let bytes = testing_bytes();
+        let filter = ChunkFilter {
+            chunk_type: Some("IDAT".to_string()),
+            index: Some(1),
+        };
+        let regions = regions(&bytes, &filter).unwrap();
+
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions[2].bytes, b"second");
+        assert!(regions[0].label.starts_with("chunk 2 [IDAT]"));
+    }
+
+    #[test]
+    fn test_hex_lines() {
+        let lines = hex_lines(b"\x89PNG\r\n\x1a\n", 0);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("00000000  89 50 4e 47 0d 0a 1a 0a"));
+        assert!(lines[0].ends_with("|.PNG....|"));
+    }
+}
@@ -0,0 +1,662 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+
+use crate::chunk::Chunk;
+use crate::chunk_data::ImageHeader;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::rewrite::{compress_image_data, image_data};
+use crate::Result;
+
+pub const GRAYSCALE: u8 = 0;
+pub const TRUECOLOR: u8 = 2;
+pub const INDEXED: u8 = 3;
+pub const GRAYSCALE_ALPHA: u8 = 4;
+pub const TRUECOLOR_ALPHA: u8 = 6;
+
+/// Starting column, starting row, column step and row step of the seven Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Decoded pixels of a png: non-interlaced scanlines without filter bytes, in the png's own
+/// color type and bit depth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(
+        width: u32,
+        height: u32,
+        color_type: u8,
+        bit_depth: u8,
+        data: Vec<u8>,
+    ) -> Result<Self> {
+        validate_format(color_type, bit_depth)?;
+
+        let image = Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            data,
+        };
+
+        if image.data.len() != image.row_bytes() * height as usize {
+            bail!(
+                "expected {} bytes of pixel data, got {}",
+                image.row_bytes() * height as usize,
+                image.data.len()
+            )
+        }
+
+        Ok(image)
+    }
+
+    /// Decodes the pixels stored in the IDAT chunks of `png`
+    pub fn decode(png: &Png) -> Result<Self> {
+        let header = png
+            .chunk_by_type("IHDR")
+            .ok_or_else(|| anyhow!("png has no IHDR chunk"))?;
+        let header = ImageHeader::from_bytes(header.data())?;
+
+        Image::from_filtered(&header, &image_data(png)?)
+    }
+
+    /// Builds an image from decompressed, filtered (and possibly interlaced) image data
+    pub fn from_filtered(header: &ImageHeader, filtered: &[u8]) -> Result<Self> {
+        validate_format(header.color_type, header.bit_depth)?;
+        if header.compression_method != 0 || header.filter_method != 0 {
+            bail!("unsupported compression or filter method")
+        }
+
+        let mut image = Self {
+            width: header.width,
+            height: header.height,
+            bit_depth: header.bit_depth,
+            color_type: header.color_type,
+            data: Vec::new(),
+        };
+
+        match header.interlace_method {
+            0 => {
+                let (data, _) = unfilter(
+                    filtered,
+                    image.row_bytes(),
+                    image.height as usize,
+                    image.filter_stride(),
+                )?;
+                image.data = data;
+            }
+            1 => image.data = image.deinterlace(filtered)?,
+            method => bail!("unknown interlace method {}", method),
+        }
+
+        Ok(image)
+    }
+
+    pub fn header(&self) -> ImageHeader {
+        ImageHeader {
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_type: self.color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        channels(self.color_type)
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        row_bytes(self.width as usize, self.bits_per_pixel())
+    }
+
+    /// Distance in bytes to the corresponding byte of the previous pixel, as used by the filters
+    fn filter_stride(&self) -> usize {
+        (self.bits_per_pixel() / 8).max(1)
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.width as usize * self.height as usize * self.channels()
+    }
+
+    /// Returns every sample in row-major order, one value per channel
+    pub fn samples(&self) -> Vec<u16> {
+        let per_row = self.width as usize * self.channels();
+        self.data
+            .chunks(self.row_bytes().max(1))
+            .take(self.height as usize)
+            .flat_map(|row| unpack_row(row, self.bit_depth, per_row))
+            .collect()
+    }
+
+    /// Builds an image of the same format from samples in the order returned by [`Image::samples`]
+    pub fn with_samples(&self, samples: &[u16]) -> Result<Self> {
+        if samples.len() != self.sample_count() {
+            bail!(
+                "expected {} samples, got {}",
+                self.sample_count(),
+                samples.len()
+            )
+        }
+
+        let per_row = self.width as usize * self.channels();
+        let data = samples
+            .chunks(per_row.max(1))
+            .take(self.height as usize)
+            .flat_map(|row| pack_row(row, self.bit_depth, self.row_bytes()))
+            .collect();
+
+        Image::new(
+            self.width,
+            self.height,
+            self.color_type,
+            self.bit_depth,
+            data,
+        )
+    }
+
+    /// Filters (with filter type None) and compresses the pixels for storage in IDAT
+    pub fn encode_image_data(&self, level: u32) -> Result<Vec<u8>> {
+        let row_bytes = self.row_bytes();
+        let filtered: Vec<u8> = (0..self.height as usize)
+            .flat_map(|row| {
+                std::iter::once(0).chain(
+                    self.data[row * row_bytes..(row + 1) * row_bytes]
+                        .iter()
+                        .copied(),
+                )
+            })
+            .collect();
+
+        compress_image_data(&filtered, level)
+    }
+
+    /// Builds a minimal png holding only IHDR, IDAT and IEND
+    pub fn to_png(&self, level: u32) -> Result<Png> {
+        Ok(Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR")?, self.header().to_bytes()),
+            Chunk::new(ChunkType::from_str("IDAT")?, self.encode_image_data(level)?),
+            Chunk::new(ChunkType::from_str("IEND")?, Vec::new()),
+        ]))
+    }
+
+    /// Converts to 8-bit RGBA, expanding the palette and applying tRNS transparency
+    pub fn to_rgba8(&self, palette: Option<&[u8]>, transparency: Option<&[u8]>) -> Result<Image> {
+        let max = (1u32 << self.bit_depth) - 1;
+        let scale = |sample: u16| (sample as u32 * 255 / max) as u8;
+        let transparent = |samples: &[u16]| match transparency {
+            Some(trns) if trns.len() == samples.len() * 2 => samples
+                .iter()
+                .enumerate()
+                .all(|(i, sample)| u16::from_be_bytes([trns[i * 2], trns[i * 2 + 1]]) == *sample),
+            _ => false,
+        };
+
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for pixel in self.samples().chunks(self.channels()) {
+            let rgba = match self.color_type {
+                GRAYSCALE => {
+                    let gray = scale(pixel[0]);
+                    let alpha = if transparent(pixel) { 0 } else { 255 };
+                    [gray, gray, gray, alpha]
+                }
+                TRUECOLOR => {
+                    let alpha = if transparent(pixel) { 0 } else { 255 };
+                    [scale(pixel[0]), scale(pixel[1]), scale(pixel[2]), alpha]
+                }
+                INDEXED => {
+                    let index = pixel[0] as usize;
+                    let palette =
+                        palette.ok_or_else(|| anyhow!("indexed image has no PLTE chunk"))?;
+                    if (index + 1) * 3 > palette.len() {
+                        bail!("palette index {} is out of range", index)
+                    }
+                    let alpha = transparency
+                        .and_then(|trns| trns.get(index))
+                        .copied()
+                        .unwrap_or(255);
+                    [
+                        palette[index * 3],
+                        palette[index * 3 + 1],
+                        palette[index * 3 + 2],
+                        alpha,
+                    ]
+                }
+                GRAYSCALE_ALPHA => {
+                    let gray = scale(pixel[0]);
+                    [gray, gray, gray, scale(pixel[1])]
+                }
+                _ => [
+                    scale(pixel[0]),
+                    scale(pixel[1]),
+                    scale(pixel[2]),
+                    scale(pixel[3]),
+                ],
+            };
+            data.extend_from_slice(&rgba);
+        }
+
+        Image::new(self.width, self.height, TRUECOLOR_ALPHA, 8, data)
+    }
+
+    fn deinterlace(&self, filtered: &[u8]) -> Result<Vec<u8>> {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let bits_per_pixel = self.bits_per_pixel();
+        let passes = adam7_passes(width, height);
+
+        // Check the dimensions claimed by IHDR against the data before allocating for them
+        match adam7_filtered_len(&passes, bits_per_pixel) {
+            Some(expected) if expected <= filtered.len() => {}
+            _ => bail!(
+                "image data is truncated: {}x{} interlaced pixels need more than {} bytes",
+                width,
+                height,
+                filtered.len()
+            ),
+        }
+        let mut output = vec![0; self.row_bytes() * height];
+        let mut offset = 0;
+
+        for (start_x, start_y, step_x, step_y, pass_width, pass_height) in passes {
+            let pass_row_bytes = row_bytes(pass_width, bits_per_pixel);
+            let (pass, consumed) = unfilter(
+                &filtered[offset.min(filtered.len())..],
+                pass_row_bytes,
+                pass_height,
+                self.filter_stride(),
+            )?;
+            offset += consumed;
+
+            for pass_y in 0..pass_height {
+                let row = &pass[pass_y * pass_row_bytes..(pass_y + 1) * pass_row_bytes];
+                let y = start_y + pass_y * step_y;
+                for pass_x in 0..pass_width {
+                    let x = start_x + pass_x * step_x;
+                    copy_pixel(
+                        row,
+                        pass_x,
+                        &mut output[y * self.row_bytes()..],
+                        x,
+                        bits_per_pixel,
+                    );
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Decodes `png` and converts it to 8-bit RGBA
+pub fn decode_rgba8(png: &Png) -> Result<Image> {
+    Image::decode(png)?.to_rgba8(
+        png.chunk_by_type("PLTE").map(|chunk| chunk.data()),
+        png.chunk_by_type("tRNS").map(|chunk| chunk.data()),
+    )
+}
+
+/// A valid 1x1 transparent png (IHDR, IDAT, IEND) to carry chunks when there is no image yet
+pub fn minimal_png() -> Result<Png> {
+    Image::new(1, 1, GRAYSCALE_ALPHA, 8, vec![0, 0])?.to_png(9)
+}
+
+pub fn channels(color_type: u8) -> usize {
+    match color_type {
+        GRAYSCALE | INDEXED => 1,
+        GRAYSCALE_ALPHA => 2,
+        TRUECOLOR => 3,
+        TRUECOLOR_ALPHA => 4,
+        _ => 0,
+    }
+}
+
+fn validate_format(color_type: u8, bit_depth: u8) -> Result<()> {
+    let allowed: &[u8] = match color_type {
+        GRAYSCALE => &[1, 2, 4, 8, 16],
+        INDEXED => &[1, 2, 4, 8],
+        TRUECOLOR | GRAYSCALE_ALPHA | TRUECOLOR_ALPHA => &[8, 16],
+        _ => bail!("unknown color type {}", color_type),
+    };
+
+    if !allowed.contains(&bit_depth) {
+        bail!(
+            "bit depth {} is invalid for color type {}",
+            bit_depth,
+            color_type
+        )
+    }
+    Ok(())
+}
+
+fn row_bytes(width: usize, bits_per_pixel: usize) -> usize {
+    (width * bits_per_pixel).div_ceil(8)
+}
+
+/// Start and step of each non-empty Adam7 pass, with the pass's width and height
+fn adam7_passes(width: usize, height: usize) -> Vec<(usize, usize, usize, usize, usize, usize)> {
+    ADAM7
+        .iter()
+        .map(|&(start_x, start_y, step_x, step_y)| {
+            let pass_width = (width + step_x - start_x - 1) / step_x;
+            let pass_height = (height + step_y - start_y - 1) / step_y;
+            (start_x, start_y, step_x, step_y, pass_width, pass_height)
+        })
+        .filter(|pass| pass.4 > 0 && pass.5 > 0)
+        .collect()
+}
+
+fn adam7_filtered_len(
+    passes: &[(usize, usize, usize, usize, usize, usize)],
+    bits_per_pixel: usize,
+) -> Option<usize> {
+    passes.iter().try_fold(0usize, |total, pass| {
+        filtered_len(pass.4, pass.5, bits_per_pixel).and_then(|length| total.checked_add(length))
+    })
+}
+
+/// Length of the decompressed image data `header` describes, which bounds how far it is inflated
+pub fn filtered_image_len(header: &ImageHeader) -> Result<usize> {
+    validate_format(header.color_type, header.bit_depth)?;
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let bits_per_pixel = channels(header.color_type) * header.bit_depth as usize;
+
+    let length = match header.interlace_method {
+        0 => filtered_len(width, height, bits_per_pixel),
+        1 => adam7_filtered_len(&adam7_passes(width, height), bits_per_pixel),
+        method => bail!("unknown interlace method {}", method),
+    };
+    length.ok_or_else(|| anyhow!("image of {}x{} pixels is too large", width, height))
+}
+
+/// Length of `rows` filtered scanlines, each with its filter type byte, or None on overflow
+fn filtered_len(width: usize, rows: usize, bits_per_pixel: usize) -> Option<usize> {
+    let row_bytes = width.checked_mul(bits_per_pixel)?.div_ceil(8);
+    row_bytes.checked_add(1)?.checked_mul(rows)
+}
+
+/// Reverses the scanline filters, returning the raw rows and the number of filtered bytes consumed
+fn unfilter(
+    filtered: &[u8],
+    row_bytes: usize,
+    rows: usize,
+    stride: usize,
+) -> Result<(Vec<u8>, usize)> {
+    let consumed = match (row_bytes + 1).checked_mul(rows) {
+        Some(consumed) if consumed <= filtered.len() => consumed,
+        _ => bail!(
+            "image data is truncated: {} rows of {} bytes need more than {} bytes",
+            rows,
+            row_bytes + 1,
+            filtered.len()
+        ),
+    };
+
+    let mut output = vec![0u8; row_bytes * rows];
+    for row in 0..rows {
+        let line = &filtered[row * (row_bytes + 1)..(row + 1) * (row_bytes + 1)];
+        let (filter, line) = (line[0], &line[1..]);
+        let (previous, current) = output.split_at_mut(row * row_bytes);
+        let previous = if row == 0 {
+            None
+        } else {
+            Some(&previous[(row - 1) * row_bytes..])
+        };
+        let current = &mut current[..row_bytes];
+
+        for i in 0..row_bytes {
+            let a = if i >= stride { current[i - stride] } else { 0 };
+            let b = previous.map_or(0, |previous| previous[i]);
+            let c = match previous {
+                Some(previous) if i >= stride => previous[i - stride],
+                _ => 0,
+            };
+
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                filter => bail!("unknown filter type {} in row {}", filter, row),
+            };
+            current[i] = line[i].wrapping_add(predictor);
+        }
+    }
+
+    Ok((output, consumed))
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn copy_pixel(
+    source: &[u8],
+    source_x: usize,
+    target: &mut [u8],
+    target_x: usize,
+    bits_per_pixel: usize,
+) {
+    if bits_per_pixel >= 8 {
+        let bytes = bits_per_pixel / 8;
+        target[target_x * bytes..(target_x + 1) * bytes]
+            .copy_from_slice(&source[source_x * bytes..(source_x + 1) * bytes]);
+        return;
+    }
+
+    let source_bit = source_x * bits_per_pixel;
+    let target_bit = target_x * bits_per_pixel;
+    let mask = (1u8 << bits_per_pixel) - 1;
+    let value = (source[source_bit / 8] >> (8 - bits_per_pixel - source_bit % 8)) & mask;
+    let shift = 8 - bits_per_pixel - target_bit % 8;
+    target[target_bit / 8] = (target[target_bit / 8] & !(mask << shift)) | (value << shift);
+}
+
+fn unpack_row(row: &[u8], bit_depth: u8, count: usize) -> Vec<u16> {
+    match bit_depth {
+        16 => row
+            .chunks(2)
+            .take(count)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect(),
+        8 => row.iter().take(count).map(|byte| *byte as u16).collect(),
+        depth => {
+            let depth = depth as usize;
+            let mask = (1u16 << depth) - 1;
+            (0..count)
+                .map(|i| {
+                    let bit = i * depth;
+                    (row[bit / 8] as u16 >> (8 - depth - bit % 8)) & mask
+                })
+                .collect()
+        }
+    }
+}
+
+fn pack_row(samples: &[u16], bit_depth: u8, row_bytes: usize) -> Vec<u8> {
+    match bit_depth {
+        16 => samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect(),
+        8 => samples.iter().map(|sample| *sample as u8).collect(),
+        depth => {
+            let depth = depth as usize;
+            let mut row = vec![0u8; row_bytes];
+            for (i, sample) in samples.iter().enumerate() {
+                let bit = i * depth;
+                let value = (*sample as u8) & ((1u8 << depth) - 1);
+                row[bit / 8] |= value << (8 - depth - bit % 8);
+            }
+            row
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32, color_type: u8, bit_depth: u8) -> Image {
+        let channels = channels(color_type) as u32;
+        let max = (1u32 << bit_depth) - 1;
+        let samples: Vec<u16> = (0..width * height * channels)
+            .map(|i| ((i * 37) % (max + 1)) as u16)
+            .collect();
+        let empty = Image {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            data: vec![
+                0;
+                row_bytes(width as usize, channels as usize * bit_depth as usize)
+                    * height as usize
+            ],
+        };
+        empty.with_samples(&samples).unwrap()
+    }
+
+    #[test]
+    fn test_minimal_png() {
+        let png = minimal_png().unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+
+        let image = Image::decode(&Png::try_from(png.as_bytes().as_slice()).unwrap()).unwrap();
+        assert_eq!((image.width, image.height), (1, 1));
+    }
+
+    #[test]
+    fn test_round_trip_formats() {
+        for (color_type, bit_depth) in [(0, 1), (0, 4), (0, 16), (2, 8), (3, 2), (4, 8), (6, 16)] {
+            let image = gradient(7, 5, color_type, bit_depth);
+            let png = image.to_png(6).unwrap();
+            let decoded = Image::decode(&png).unwrap();
+            assert_eq!(
+                decoded, image,
+                "color type {} depth {}",
+                color_type, bit_depth
+            );
+        }
+    }
+
+    #[test]
+    fn test_unfilter_all_filters() {
+        // Two rows of two RGB pixels, encoded with the sub and paeth filters
+        let raw = [10u8, 20, 30, 40, 50, 60, 15, 25, 35, 45, 55, 65];
+        let mut filtered = vec![1];
+        filtered.extend([10, 20, 30, 30, 30, 30]);
+        filtered.push(4);
+        for i in 0..6 {
+            let a = if i >= 3 { raw[6 + i - 3] } else { 0 };
+            let b = raw[i];
+            let c = if i >= 3 { raw[i - 3] } else { 0 };
+            filtered.push(raw[6 + i].wrapping_sub(paeth(a, b, c)));
+        }
+
+        let (data, consumed) = unfilter(&filtered, 6, 2, 3).unwrap();
+        assert_eq!(data, raw);
+        assert_eq!(consumed, filtered.len());
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        let image = gradient(9, 9, 2, 8);
+        let bytes_per_pixel = 3;
+        let mut filtered = Vec::new();
+        for (start_x, start_y, step_x, step_y) in ADAM7 {
+            for y in (start_y..9).step_by(step_y) {
+                filtered.push(0);
+                for x in (start_x..9).step_by(step_x) {
+                    let offset = y * image.row_bytes() + x * bytes_per_pixel;
+                    filtered.extend_from_slice(&image.data[offset..offset + bytes_per_pixel]);
+                }
+            }
+        }
+
+        let mut header = image.header();
+        header.interlace_method = 1;
+        assert_eq!(Image::from_filtered(&header, &filtered).unwrap(), image);
+    }
+
+    #[test]
+    fn test_hostile_dimensions() {
+        let filtered = [0u8; 64];
+        for interlace_method in [0, 1] {
+            let header = ImageHeader {
+                width: u32::MAX,
+                height: u32::MAX,
+                bit_depth: 16,
+                color_type: TRUECOLOR_ALPHA,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method,
+            };
+            assert!(Image::from_filtered(&header, &filtered).is_err());
+        }
+
+        let mut header = gradient(9, 9, 2, 8).header();
+        header.interlace_method = 1;
+        assert!(Image::from_filtered(&header, &filtered).is_err());
+    }
+
+    #[test]
+    fn test_to_rgba8() {
+        let indexed = Image::new(2, 1, INDEXED, 8, vec![1, 0]).unwrap();
+        let rgba = indexed
+            .to_rgba8(Some(&[10, 20, 30, 40, 50, 60]), Some(&[0]))
+            .unwrap();
+        assert_eq!(rgba.data, [40, 50, 60, 255, 10, 20, 30, 0]);
+
+        let gray = Image::new(2, 1, GRAYSCALE, 4, vec![0xf0]).unwrap();
+        let rgba = gray.to_rgba8(None, Some(&[0, 0])).unwrap();
+        assert_eq!(rgba.data, [255, 255, 255, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_fixture() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+        let image = Image::decode(&png).unwrap();
+        assert_eq!((image.width, image.height), (50, 50));
+        assert_eq!(image.samples().len(), 50 * 50 * 4);
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+use anyhow::bail;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, IMAGE_END_CHUNK_TYPE};
+use crate::Result;
+
+/// Location of a chunk found by reading only chunk headers
+#[derive(Debug, Clone)]
+pub struct ChunkHeader {
+    pub offset: u64,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+impl ChunkHeader {
+    pub fn end(&self) -> u64 {
+        self.offset + Chunk::METADATA_BYTES as u64 + self.length as u64
+    }
+}
+
+/// Reads the chunk headers of a png up to and including IEND, seeking over chunk data so large
+/// files are never read in full
+pub fn scan_headers<R: Read + Seek>(reader: R) -> Result<Vec<ChunkHeader>> {
+    let mut reader = BufReader::new(reader);
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut signature = [0; Png::STANDARD_HEADER_LENGTH];
+    reader.read_exact(&mut signature)?;
+    if &signature != Png::STANDARD_HEADER {
+        bail!("invalid png file header")
+    }
+
+    let mut headers = Vec::new();
+    let mut offset = Png::STANDARD_HEADER_LENGTH as u64;
+    loop {
+        let mut header = [0; 8];
+        if let Err(error) = reader.read_exact(&mut header) {
+            if error.kind() == std::io::ErrorKind::UnexpectedEof {
+                bail!("png has no IEND chunk")
+            }
+            return Err(error.into());
+        }
+
+        let chunk = ChunkHeader {
+            offset,
+            length: u32::from_be_bytes(header[0..4].try_into()?),
+            chunk_type: ChunkType::try_from(<[u8; 4]>::try_from(&header[4..8])?)?,
+        };
+        reader.seek_relative(chunk.length as i64 + Chunk::CRC_BYTES as i64)?;
+        offset = chunk.end();
+
+        let is_end = chunk.chunk_type.to_string() == IMAGE_END_CHUNK_TYPE;
+        headers.push(chunk);
+        if is_end {
+            return Ok(headers);
+        }
+    }
+}
+
+fn end_chunk() -> Result<Chunk> {
+    Ok(Chunk::new(IMAGE_END_CHUNK_TYPE.parse()?, Vec::new()))
+}
+
+/// Writes `bytes` followed by IEND and the existing trailer at `offset`, then truncates the file
+/// after them
+fn rewrite_tail(file: &mut File, offset: u64, end: &ChunkHeader, bytes: &[u8]) -> Result<()> {
+    let mut trailer = Vec::new();
+    file.seek(SeekFrom::Start(end.end()))?;
+    file.read_to_end(&mut trailer)?;
+
+    let mut tail = bytes.to_vec();
+    tail.extend(end_chunk()?.as_bytes());
+    tail.extend(trailer);
+
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&tail)?;
+    file.set_len(offset + tail.len() as u64)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Inserts `chunks` before IEND by overwriting IEND, without rewriting the rest of the file
+pub fn append_chunks(file: &mut File, chunks: &[Chunk]) -> Result<()> {
+    let headers = scan_headers(&mut *file)?;
+    let end = headers.last().unwrap();
+
+    let bytes: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.as_bytes()).collect();
+    rewrite_tail(file, end.offset, end, &bytes)
+}
+
+/// Removes the chunk right before IEND by truncating the file at its offset. Fails if the last
+/// chunk is of another type, since removing an earlier chunk means moving everything after it.
+pub fn remove_last_chunk(file: &mut File, chunk_type: &str) -> Result<Chunk> {
+    let headers = scan_headers(&mut *file)?;
+    let [.., last, end] = headers.as_slice() else {
+        bail!("png has no chunk before IEND")
+    };
+    if last.chunk_type.to_string() != chunk_type {
+        bail!(
+            "the last chunk before IEND is [{}], not [{}]; only the last chunk can be removed in place",
+            last.chunk_type,
+            chunk_type
+        )
+    }
+
+    let mut bytes = vec![0; (last.end() - last.offset) as usize];
+    file.seek(SeekFrom::Start(last.offset))?;
+    file.read_exact(&mut bytes)?;
+    let chunk = Chunk::try_from(bytes.as_slice())?;
+
+    rewrite_tail(file, last.offset, end, &[])?;
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+
+    fn testing_file(name: &str, bytes: &[u8]) -> (PathBuf, File) {
+        let path =
+            std::env::temp_dir().join(format!("pngme-inplace-{}-{}.png", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_scan_headers() {
+        let headers = scan_headers(std::io::Cursor::new(&PNG_FILE[..])).unwrap();
+        let layout = Png::layout(&PNG_FILE).unwrap();
+
+        assert_eq!(headers.len(), layout.len());
+        for (header, layout) in headers.iter().zip(&layout) {
+            assert_eq!(header.offset as usize, layout.offset);
+            assert_eq!(header.end() as usize, layout.end());
+        }
+    }
+
+    #[test]
+    fn test_append_matches_full_rewrite() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailer");
+        let (path, mut file) = testing_file("append", &bytes);
+
+        let chunks = [chunk("tEXt", b"Comment\0hi"), chunk("ruSt", b"message")];
+        append_chunks(&mut file, &chunks).unwrap();
+
+        let mut expected = Png::try_from(bytes.as_slice()).unwrap();
+        for chunk in chunks {
+            expected.append_chunk(chunk);
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), expected.as_bytes());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_last_chunk() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk("ruSt", b"message"));
+        let (path, mut file) = testing_file("remove", &png.as_bytes());
+
+        assert!(remove_last_chunk(&mut file, "IDAT").is_err());
+        let removed = remove_last_chunk(&mut file, "ruSt").unwrap();
+
+        assert_eq!(removed.data(), b"message");
+        assert_eq!(std::fs::read(&path).unwrap(), PNG_FILE);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,7 +1,26 @@
 pub use anyhow::{Error, Result};
 
+pub mod apng;
+pub mod atomic;
+pub mod batch;
 pub mod chunk;
+pub mod chunk_data;
 pub mod chunk_type;
 pub mod commands;
+pub mod compare;
+pub mod diff;
+pub mod dump;
+pub mod exif;
+pub mod hexdump;
+pub mod image;
+pub mod inplace;
+pub mod output;
 pub mod png;
+pub mod registry;
+pub mod repair;
+pub mod rewrite;
+pub mod scan;
+pub mod spread;
+pub mod strip;
+pub mod trailer;
 pub mod util;
@@ -0,0 +1,543 @@
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::apng::Frame;
+use crate::chunk::Chunk;
+use crate::commands::STDIO_PATH;
+use crate::compare::Comparison;
+use crate::exif::{ByteOrder, Directory, Value};
+use crate::hexdump::Region;
+use crate::repair::Fix;
+use crate::Result;
+
+/// Renders a command result either as human readable text or as a json document
+pub fn render<T: Serialize + Display>(output: &T, json: bool) -> Result<String> {
+    if json {
+        Ok(serde_json::to_string_pretty(output)?)
+    } else {
+        Ok(output.to_string())
+    }
+}
+
+#[derive(Serialize)]
+pub struct ErrorOutput {
+    pub error: String,
+    pub causes: Vec<String>,
+}
+
+impl From<&anyhow::Error> for ErrorOutput {
+    fn from(error: &anyhow::Error) -> Self {
+        Self {
+            error: error.to_string(),
+            causes: error
+                .chain()
+                .skip(1)
+                .map(|cause| cause.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Display for ErrorOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.error)?;
+        self.causes
+            .iter()
+            .try_for_each(|cause| write!(f, "\n  caused by: {}", cause))
+    }
+}
+
+/// Error carrying already rendered output and the process exit code to finish with
+#[derive(Debug)]
+pub struct Exit {
+    pub output: String,
+    pub code: i32,
+}
+
+impl Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.output)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+#[derive(Serialize)]
+pub struct EncodeOutput {
+    pub file_path: PathBuf,
+    pub chunk_type: String,
+    pub length: usize,
+}
+
+impl Display for EncodeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.file_path.as_os_str() == STDIO_PATH {
+            write!(f, "wrote message to standard output")
+        } else {
+            write!(f, "wrote message to file {}", self.file_path.display())
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WriteOutput {
+    pub file_path: PathBuf,
+    pub size: usize,
+}
+
+impl Display for WriteOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "wrote {} bytes to {}",
+            self.size,
+            self.file_path.display()
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct DecodeOutput {
+    pub chunk_type: String,
+    pub message: String,
+}
+
+impl Display for DecodeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.chunk_type, self.message)
+    }
+}
+
+#[derive(Serialize)]
+pub struct RemoveOutput {
+    pub chunk_type: String,
+    pub message: String,
+}
+
+impl Display for RemoveOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "removed chunk: [{}]: [{}]",
+            self.chunk_type, self.message
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct ChunkListing {
+    pub index: usize,
+    pub offset: usize,
+    pub length: usize,
+    pub chunk_type: String,
+    pub stored_crc: u32,
+    pub crc_valid: bool,
+    pub properties: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub specification: Option<String>,
+    /// The line printed by `list`, from `registry::describe`
+    #[serde(skip)]
+    pub summary: String,
+}
+
+#[derive(Serialize)]
+pub struct ChunkList {
+    pub chunks: Vec<ChunkListing>,
+    pub file_size: usize,
+    pub data_size: usize,
+    /// Bytes after IEND
+    pub trailer_size: usize,
+}
+
+impl ChunkList {
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec![
+            "index,offset,length,chunk_type,stored_crc,crc_valid,properties,description,specification"
+                .to_string(),
+        ];
+
+        lines.extend(self.chunks.iter().map(|chunk| {
+            format!(
+                "{},{},{},{},{:08x},{},\"{}\",\"{}\",{}",
+                chunk.index,
+                chunk.offset,
+                chunk.length,
+                chunk.chunk_type,
+                chunk.stored_crc,
+                chunk.crc_valid,
+                chunk.properties,
+                chunk.description,
+                chunk.specification.as_deref().unwrap_or_default()
+            )
+        }));
+
+        lines.join("\n")
+    }
+}
+
+impl Display for ChunkList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>10} {:>10}  {:8}  {:6}  CHUNK",
+            "INDEX", "OFFSET", "LENGTH", "CRC", "STATUS"
+        )?;
+
+        for chunk in &self.chunks {
+            writeln!(
+                f,
+                "{:>5} {:>10} {:>10}  {:08x}  {:6}  {}",
+                chunk.index,
+                chunk.offset,
+                chunk.length,
+                chunk.stored_crc,
+                if chunk.crc_valid { "ok" } else { "BAD" },
+                chunk.summary
+            )?;
+        }
+
+        write!(
+            f,
+            "{} chunks, {} bytes total ({} bytes of chunk data)",
+            self.chunks.len(),
+            self.file_size,
+            self.data_size
+        )?;
+        if self.trailer_size > 0 {
+            write!(
+                f,
+                "\n{} bytes of trailing data after IEND",
+                self.trailer_size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct RemovedChunk {
+    pub chunk_type: String,
+    /// Bytes the chunk occupied in the file, including length, type and crc
+    pub size: usize,
+}
+
+impl From<&Chunk> for RemovedChunk {
+    fn from(chunk: &Chunk) -> Self {
+        Self {
+            chunk_type: chunk.chunk_type().to_string(),
+            size: chunk.length() + Chunk::METADATA_BYTES,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StripOutput {
+    pub dry_run: bool,
+    pub removed: Vec<RemovedChunk>,
+    pub bytes_saved: usize,
+}
+
+impl Display for StripOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.dry_run {
+            "would remove"
+        } else {
+            "removed"
+        };
+
+        for chunk in &self.removed {
+            writeln!(
+                f,
+                "{} chunk: [{}] ({} bytes)",
+                verb, chunk.chunk_type, chunk.size
+            )?;
+        }
+
+        write!(
+            f,
+            "{} {} chunks, {} bytes",
+            verb,
+            self.removed.len(),
+            self.bytes_saved
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct RecompressOutput {
+    pub discarded: Vec<String>,
+    pub original_size: usize,
+    pub new_size: usize,
+}
+
+impl Display for RecompressOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk_type in &self.discarded {
+            writeln!(f, "discarded unsafe-to-copy chunk: [{}]", chunk_type)?;
+        }
+
+        write!(
+            f,
+            "recompressed image data: {} -> {} bytes",
+            self.original_size, self.new_size
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct HexdumpOutput {
+    pub regions: Vec<Region>,
+}
+
+impl Display for HexdumpOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regions: Vec<String> = self.regions.iter().map(|r| r.to_string()).collect();
+        write!(f, "{}", regions.join("\n"))
+    }
+}
+
+#[derive(Serialize)]
+pub struct CompareOutput {
+    #[serde(flatten)]
+    pub comparison: Comparison,
+    pub diff_map: Option<PathBuf>,
+    /// False when the difference exceeds the requested threshold
+    pub within_threshold: bool,
+}
+
+impl Display for CompareOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.comparison)?;
+        if let Some(diff_map) = &self.diff_map {
+            write!(f, "\ndifference map written to {}", diff_map.display())?;
+        }
+        if !self.within_threshold {
+            write!(f, "\ndifference exceeds threshold")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct RepairOutput {
+    pub file_path: PathBuf,
+    pub size: usize,
+    pub fixes: Vec<Fix>,
+}
+
+impl Display for RepairOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.fixes.is_empty() {
+            writeln!(f, "no damage found")?;
+        }
+        self.fixes
+            .iter()
+            .try_for_each(|fix| writeln!(f, "{}", fix))?;
+        write!(
+            f,
+            "wrote {} bytes to {}",
+            self.size,
+            self.file_path.display()
+        )
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TrailerAction {
+    Extracted { file_path: PathBuf },
+    Stripped,
+    Written { length: usize },
+}
+
+#[derive(Serialize)]
+pub struct TrailerOutput {
+    pub offset: usize,
+    pub length: usize,
+    pub format: Option<&'static str>,
+    /// Types of the chunks at the start of the trailer, written after IEND by earlier versions
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub legacy_chunks: Vec<String>,
+    #[serde(flatten)]
+    pub action: Option<TrailerAction>,
+}
+
+impl Display for TrailerOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.length == 0 {
+            write!(f, "no data after IEND")?;
+        } else {
+            write!(
+                f,
+                "{} bytes after IEND at offset {} ({})",
+                self.length,
+                self.offset,
+                self.format.unwrap_or("unknown format")
+            )?;
+        }
+        if !self.legacy_chunks.is_empty() {
+            write!(
+                f,
+                "\nstarts with chunks appended after IEND by earlier pngme versions: [{}]; \
+                 decode still finds them there, --strip removes them",
+                self.legacy_chunks.join("], [")
+            )?;
+        }
+
+        match &self.action {
+            Some(TrailerAction::Extracted { file_path }) => {
+                write!(f, "\nextracted to {}", file_path.display())
+            }
+            Some(TrailerAction::Stripped) => write!(f, "\nstripped"),
+            Some(TrailerAction::Written { length }) => {
+                write!(f, "\nreplaced with {} bytes", length)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FramesOutput {
+    pub num_frames: u32,
+    /// Number of times the animation loops, 0 for forever
+    pub num_plays: u32,
+    pub default_image_is_frame: bool,
+    pub frames: Vec<Frame>,
+}
+
+impl Display for FramesOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>11} {:>11} {:>8}  {:10}  {:6}  CHUNKS",
+            "FRAME", "SIZE", "OFFSET", "DELAY", "DISPOSE", "BLEND"
+        )?;
+
+        for frame in &self.frames {
+            writeln!(
+                f,
+                "{:>5} {:>11} {:>11} {:>7.3}s  {:10}  {:6}  {}{}",
+                frame.index,
+                format!("{}x{}", frame.control.width, frame.control.height),
+                format!("{},{}", frame.control.x_offset, frame.control.y_offset),
+                frame.delay,
+                frame.control.dispose_op.to_string(),
+                frame.control.blend_op.to_string(),
+                frame.data_chunks.len(),
+                if frame.is_default_image {
+                    " (default image)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+
+        write!(f, "{} frames, ", self.num_frames)?;
+        match self.num_plays {
+            0 => write!(f, "looping forever")?,
+            plays => write!(f, "played {} times", plays)?,
+        }
+        if !self.default_image_is_frame {
+            write!(f, "\ndefault image is not part of the animation")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct SplitOutput {
+    pub frames: Vec<WriteOutput>,
+}
+
+impl Display for SplitOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for frame in &self.frames {
+            writeln!(f, "{}", frame)?;
+        }
+        write!(f, "{} frames", self.frames.len())
+    }
+}
+
+#[derive(Serialize)]
+pub struct ExifField {
+    pub ifd: Directory,
+    pub tag: u16,
+    pub name: Option<&'static str>,
+    #[serde(flatten)]
+    pub value: Value,
+}
+
+#[derive(Serialize)]
+pub struct ExifOutput {
+    pub byte_order: ByteOrder,
+    pub fields: Vec<ExifField>,
+}
+
+impl Display for ExifOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:4}  {:6}  {:24}  VALUE", "IFD", "TAG", "NAME")?;
+        for field in &self.fields {
+            writeln!(
+                f,
+                "{:4}  {:#06x}  {:24}  {}",
+                field.ifd.to_string(),
+                field.tag,
+                field.name.unwrap_or("unknown"),
+                field.value
+            )?;
+        }
+        write!(f, "{} fields", self.fields.len())
+    }
+}
+
+#[derive(Serialize)]
+pub struct ExifRemoveOutput {
+    pub gps_only: bool,
+    /// False if there was nothing to remove and the file was left alone
+    pub changed: bool,
+    pub removed_fields: usize,
+}
+
+impl Display for ExifRemoveOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.gps_only, self.changed) {
+            (true, false) => write!(f, "no gps fields to remove"),
+            (true, true) => write!(f, "removed {} gps fields", self.removed_fields),
+            (false, false) => write!(f, "no eXIf chunk to remove"),
+            (false, true) => write!(f, "removed eXIf chunk with {} fields", self.removed_fields),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let output = DecodeOutput {
+            chunk_type: "ruSt".to_string(),
+            message: "hello".to_string(),
+        };
+
+        assert_eq!(render(&output, false).unwrap(), "ruSt: hello");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&output, true).unwrap()).unwrap();
+        assert_eq!(json["chunk_type"], "ruSt");
+        assert_eq!(json["message"], "hello");
+    }
+
+    #[test]
+    fn test_error_output() {
+        let error = anyhow::anyhow!("inner").context("outer");
+        let output = ErrorOutput::from(&error);
+
+        assert_eq!(output.error, "outer");
+        assert_eq!(output.causes, ["inner"]);
+    }
+}
@@ -5,10 +5,53 @@ use std::fmt::Display;
 use std::fs::File;
 use std::io::Read;
 
-use crate::chunk::Chunk;
+use memmap2::Mmap;
+
+use crate::chunk::{Chunk, ChunkRef, ParseOptions, Serialisation};
+use crate::chunk_type::ChunkType;
 use crate::{Error, Result};
+
+/// The chunk that ends a png; anything after it is kept as the trailer
+pub const IMAGE_END_CHUNK_TYPE: &str = "IEND";
+
+/// Position of a chunk within a png byte stream, as found by [`Png::layout`]
+#[derive(Debug, Clone)]
+pub struct ChunkLayout {
+    pub index: usize,
+    /// Offset of the chunk's length field from the start of the file
+    pub offset: usize,
+    pub length: usize,
+    pub chunk_type: ChunkType,
+    pub stored_crc: u32,
+}
+
+impl ChunkLayout {
+    pub fn data_offset(&self) -> usize {
+        self.offset + Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES
+    }
+
+    pub fn crc_offset(&self) -> usize {
+        self.data_offset() + self.length
+    }
+
+    /// Offset one past the last byte of the chunk
+    pub fn end(&self) -> usize {
+        self.crc_offset() + Chunk::CRC_BYTES
+    }
+
+    pub fn data<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[self.data_offset()..self.crc_offset()]
+    }
+
+    /// Computes the crc of the chunk data in `bytes`, the buffer this layout was read from
+    pub fn computed_crc(&self, bytes: &[u8]) -> u32 {
+        Chunk::checksum(&self.chunk_type, self.data(bytes))
+    }
+}
+
 pub struct Png {
     chunks: Vec<Chunk>,
+    trailer: Vec<u8>,
 }
 
 impl Png {
@@ -16,11 +59,25 @@ impl Png {
     pub const STANDARD_HEADER_LENGTH: usize = Png::STANDARD_HEADER.len();
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
-        Self { chunks }
+        Self {
+            chunks,
+            trailer: Vec::new(),
+        }
     }
 
+    /// Adds a chunk at the end of the png, keeping IEND as the last chunk if there is one
     pub fn append_chunk(&mut self, chunk: Chunk) {
-        self.chunks.push(chunk)
+        let index = match self.chunks.last() {
+            Some(last) if last.chunk_type().to_string() == IMAGE_END_CHUNK_TYPE => {
+                self.chunks.len() - 1
+            }
+            _ => self.chunks.len(),
+        };
+        self.chunks.insert(index, chunk)
+    }
+
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index, chunk)
     }
 
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk> {
@@ -32,14 +89,115 @@ impl Png {
         Ok(self.chunks.remove(index))
     }
 
+    /// Removes every chunk for which `predicate` returns true, returning the removed chunks in order
+    pub fn remove_chunks_where<F>(&mut self, mut predicate: F) -> Vec<Chunk>
+    where
+        F: FnMut(&Chunk) -> bool,
+    {
+        let (removed, kept) = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .partition(|chunk| predicate(chunk));
+        self.chunks = kept;
+        removed
+    }
+
     pub fn header(&self) -> &[u8; 8] {
         Png::STANDARD_HEADER
     }
 
+    /// Bytes found after the IEND chunk
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    pub fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = trailer
+    }
+
+    pub fn take_trailer(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.trailer)
+    }
+
+    /// Offset of the trailer in a file with the given layout
+    pub fn trailer_offset(layout: &[ChunkLayout]) -> usize {
+        layout
+            .last()
+            .map_or(Png::STANDARD_HEADER_LENGTH, |chunk| chunk.end())
+    }
+
+    /// Walks the chunks of a png byte stream without verifying their crcs, stopping after IEND
+    pub fn layout(bytes: &[u8]) -> Result<Vec<ChunkLayout>> {
+        let mut layout = Vec::new();
+        Png::walk_layout(bytes, &mut layout)?;
+        Ok(layout)
+    }
+
+    /// Like [`Png::layout`], but keeps the chunks read before a truncated or corrupt one, along
+    /// with the error that stopped the walk
+    pub fn partial_layout(bytes: &[u8]) -> (Vec<ChunkLayout>, Option<anyhow::Error>) {
+        let mut layout = Vec::new();
+        let error = Png::walk_layout(bytes, &mut layout).err();
+        (layout, error)
+    }
+
+    fn walk_layout(bytes: &[u8], layout: &mut Vec<ChunkLayout>) -> Result<()> {
+        if bytes.len() < Png::STANDARD_HEADER_LENGTH {
+            bail!("value length lower than minimum header length");
+        }
+
+        if &bytes[..Png::STANDARD_HEADER_LENGTH] != Png::STANDARD_HEADER {
+            bail!("invalid png file header")
+        }
+
+        let mut offset = Png::STANDARD_HEADER_LENGTH;
+        while offset < bytes.len() {
+            let remaining = &bytes[offset..];
+            if remaining.len() < Chunk::METADATA_BYTES {
+                bail!("truncated chunk at offset {}", offset)
+            }
+
+            let length = u32::from_be_bytes(remaining[0..4].try_into()?) as usize;
+            let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&remaining[4..8])?)?;
+
+            if remaining.len() < length + Chunk::METADATA_BYTES {
+                bail!("chunk [{}] at offset {} is truncated", chunk_type, offset)
+            }
+
+            let crc_start = Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES + length;
+            let stored_crc =
+                u32::from_be_bytes(remaining[crc_start..crc_start + Chunk::CRC_BYTES].try_into()?);
+
+            layout.push(ChunkLayout {
+                index: layout.len(),
+                offset,
+                length,
+                chunk_type,
+                stored_crc,
+            });
+
+            offset += length + Chunk::METADATA_BYTES;
+            if layout.last().unwrap().chunk_type.to_string() == IMAGE_END_CHUNK_TYPE {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
     }
 
+    /// Parses a png, verifying crcs only if `options` asks for it
+    pub fn parse(bytes: &[u8], options: ParseOptions) -> Result<Self> {
+        PngRef::parse(bytes, options).map(Png::from)
+    }
+
+    /// Checks the stored crc of every parsed chunk, for pngs parsed without verification
+    pub fn verify_crc(&self) -> Result<()> {
+        self.chunks.iter().try_for_each(Chunk::verify_crc)
+    }
+
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         self.chunks
             .iter()
@@ -47,14 +205,24 @@ impl Png {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        self.to_bytes(Serialisation::Canonical)
+    }
+
+    /// Serialises the png. [`Serialisation::AsRead`] reproduces a parsed file exactly as long as
+    /// its chunks have not been changed.
+    pub fn to_bytes(&self, serialisation: Serialisation) -> Vec<u8> {
         let header: Vec<u8> = self.header().iter().copied().collect();
         let body: Vec<u8> = self
             .chunks
             .iter()
-            .flat_map(|c| c.as_bytes().into_iter())
+            .flat_map(|c| c.to_bytes(serialisation).into_iter())
             .collect();
 
-        header.into_iter().chain(body.into_iter()).collect()
+        header
+            .into_iter()
+            .chain(body.into_iter())
+            .chain(self.trailer.iter().copied())
+            .collect()
     }
 }
 
@@ -62,26 +230,7 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        if value.len() < Png::STANDARD_HEADER_LENGTH {
-            return Err(anyhow!("value length lower than minimum header length"));
-        }
-        let (header, value) = value.split_at(Png::STANDARD_HEADER_LENGTH);
-
-        if header != Png::STANDARD_HEADER {
-            bail!("invalid png file header")
-        }
-
-        let mut chunks = Vec::<Chunk>::new();
-
-        let mut index = 0;
-        while index < value.len() {
-            let bytes = &value[index..];
-            let chunk = Chunk::try_from(bytes)?;
-
-            index += chunk.length() + Chunk::METADATA_BYTES;
-            chunks.push(chunk);
-        }
-        Ok(Self { chunks })
+        PngRef::try_from(value).map(Png::from)
     }
 }
 
@@ -97,6 +246,75 @@ impl TryFrom<&mut File> for Png {
     }
 }
 
+/// A png borrowing its chunks from the buffer it was parsed from, so chunk data is never copied
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+    trailer: &'a [u8],
+}
+
+impl<'a> PngRef<'a> {
+    /// Parses a png from `bytes`, verifying crcs only if `options` asks for it
+    pub fn parse(bytes: &'a [u8], options: ParseOptions) -> Result<Self> {
+        let layout = Png::layout(bytes)?;
+        let chunks = layout
+            .iter()
+            .map(|layout| ChunkRef::parse_with(bytes, layout.offset, options))
+            .collect::<Result<Vec<ChunkRef>>>()?;
+
+        Ok(Self {
+            chunks,
+            trailer: &bytes[Png::trailer_offset(&layout)..],
+        })
+    }
+
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().to_string().eq(chunk_type))
+    }
+
+    /// Bytes found after the IEND chunk
+    pub fn trailer(&self) -> &'a [u8] {
+        self.trailer
+    }
+
+    /// Copies the chunks into an owned png
+    pub fn to_png(&self) -> Png {
+        Png {
+            chunks: self.chunks.iter().map(ChunkRef::to_chunk).collect(),
+            trailer: self.trailer.to_vec(),
+        }
+    }
+
+    /// Maps `file` into memory so a [`PngRef`] can be parsed from it without reading it
+    pub fn map(file: &File) -> Result<Mmap> {
+        // SAFETY: the mapping is only read. Changing the file while it is mapped may show the
+        // new contents or fail with a bus error, the same caveat as any memory-mapped reader.
+        Ok(unsafe { Mmap::map(file)? })
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self> {
+        PngRef::parse(value, ParseOptions::default())
+    }
+}
+
+impl From<PngRef<'_>> for Png {
+    fn from(png: PngRef<'_>) -> Self {
+        Png {
+            chunks: png.chunks.into_iter().map(Chunk::from).collect(),
+            trailer: png.trailer.to_vec(),
+        }
+    }
+}
+
 impl Display for Png {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.chunks.iter().fold(Ok(()), |result, chunk| {
@@ -106,7 +324,7 @@ impl Display for Png {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
@@ -228,6 +446,140 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_append_chunk_before_end() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .rev()
+            .take(2)
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IEND", "TeSt"]);
+    }
+
+    #[test]
+    fn test_trailer() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"PK\x03\x04 not a chunk");
+
+        let mut png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.trailer(), b"PK\x03\x04 not a chunk");
+        assert_eq!(png.as_bytes(), bytes);
+
+        let layout = Png::layout(&bytes).unwrap();
+        assert_eq!(Png::trailer_offset(&layout), PNG_FILE.len());
+
+        png.take_trailer();
+        assert_eq!(png.as_bytes(), PNG_FILE);
+    }
+
+    #[test]
+    fn test_png_ref() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailer");
+
+        let png = PngRef::try_from(bytes.as_slice()).unwrap();
+        let idat = png.chunk_by_type("IDAT").unwrap();
+        assert_eq!(idat.data().as_ptr(), bytes[idat.offset() + 8..].as_ptr());
+        assert_eq!(png.trailer(), b"trailer");
+        assert_eq!(png.to_png().as_bytes(), bytes);
+
+        let path = std::env::temp_dir().join(format!("pngme-map-{}.png", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let map = PngRef::map(&File::open(&path).unwrap()).unwrap();
+        let mapped = PngRef::try_from(&map[..]).unwrap();
+        assert_eq!(mapped.chunks().len(), png.chunks().len());
+        assert_eq!(Png::from(mapped).as_bytes(), bytes);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_without_crc_verification() {
+        let mut bytes = PNG_FILE.to_vec();
+        let idat = Png::layout(&bytes)
+            .unwrap()
+            .into_iter()
+            .find(|layout| layout.chunk_type.to_string() == "IDAT")
+            .unwrap();
+        bytes[idat.crc_offset()] ^= 0xff;
+
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+        let png = Png::parse(&bytes, ParseOptions { verify_crc: false }).unwrap();
+        assert!(png.verify_crc().is_err());
+        assert_eq!(
+            png.chunk_by_type("IDAT").unwrap().stored_crc(),
+            Some(idat.stored_crc ^ 0xff00_0000)
+        );
+    }
+
+    /// Real and generated pngs with the quirks a lenient parser must carry through unchanged
+    fn round_trip_corpus() -> Vec<Vec<u8>> {
+        let mut trailing = PNG_FILE.to_vec();
+        trailing.extend_from_slice(b"PK\x03\x04 appended archive");
+
+        let mut bad_crc = PNG_FILE.to_vec();
+        let idat = Png::layout(&bad_crc)
+            .unwrap()
+            .into_iter()
+            .find(|layout| layout.chunk_type.to_string() == "IDAT")
+            .unwrap();
+        bad_crc[idat.crc_offset()] ^= 0x5a;
+
+        let pixels = (0..4 * 2 * 6).map(|byte| byte as u8).collect();
+        let mut wide = crate::image::Image::new(4, 2, crate::image::TRUECOLOR, 16, pixels)
+            .unwrap()
+            .to_png(0)
+            .unwrap();
+        wide.append_chunk(chunk_from_strings("tEXt", "Comment\0round trip").unwrap());
+
+        let mut corpus = vec![
+            PNG_FILE.to_vec(),
+            trailing,
+            bad_crc,
+            crate::image::minimal_png().unwrap().as_bytes(),
+            wide.as_bytes(),
+        ];
+        corpus.extend(third_party_corpus());
+        corpus
+    }
+
+    /// Pngs written by other encoders, see tests/corpus/README.md
+    fn third_party_corpus() -> Vec<Vec<u8>> {
+        let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+        let mut paths: Vec<_> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "the third party corpus is missing");
+
+        paths
+            .iter()
+            .map(|path| std::fs::read(path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_as_read_round_trip() {
+        let lenient = ParseOptions { verify_crc: false };
+        for bytes in round_trip_corpus() {
+            let png = Png::parse(&bytes, lenient).unwrap();
+            assert_eq!(png.to_bytes(Serialisation::AsRead), bytes);
+
+            let layout = Png::layout(&bytes).unwrap();
+            let offsets: Vec<_> = png.chunks().iter().map(Chunk::offset).collect();
+            let expected: Vec<_> = layout.iter().map(|chunk| Some(chunk.offset)).collect();
+            assert_eq!(offsets, expected);
+
+            let canonical = png.to_bytes(Serialisation::Canonical);
+            assert_eq!(canonical == bytes, png.verify_crc().is_ok());
+        }
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -251,6 +603,42 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_layout() {
+        let layout = Png::layout(&PNG_FILE[..]).unwrap();
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        assert_eq!(layout.len(), png.chunks().len());
+        assert_eq!(layout[0].offset, Png::STANDARD_HEADER_LENGTH);
+        assert_eq!(layout[0].chunk_type.to_string(), "IHDR");
+        assert_eq!(layout[0].length, 13);
+        assert_eq!(layout.last().unwrap().end(), PNG_FILE.len());
+
+        for (layout, chunk) in layout.iter().zip(png.chunks()) {
+            assert_eq!(layout.data(&PNG_FILE[..]), chunk.data());
+            assert_eq!(layout.stored_crc, layout.computed_crc(&PNG_FILE[..]));
+        }
+    }
+
+    #[test]
+    fn test_layout_truncated() {
+        let bytes = &PNG_FILE[..PNG_FILE.len() - 3];
+        assert!(Png::layout(bytes).is_err());
+    }
+
+    #[test]
+    fn test_partial_layout() {
+        let complete = Png::layout(&PNG_FILE[..]).unwrap();
+        let (layout, error) = Png::partial_layout(&PNG_FILE[..PNG_FILE.len() - 3]);
+
+        assert!(error.is_some());
+        assert_eq!(layout.len(), complete.len() - 1);
+
+        let (layout, error) = Png::partial_layout(&PNG_FILE[..]);
+        assert!(error.is_none());
+        assert_eq!(layout.len(), complete.len());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -270,7 +658,7 @@ mod tests {
     }
 
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
-    const PNG_FILE: [u8; 4803] = [
+    pub(crate) const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
         6, 0, 0, 0, 30, 63, 136, 177, 0, 0, 0, 1, 115, 82, 71, 66, 0, 174, 206, 28, 233, 0, 0, 0,
         4, 103, 65, 77, 65, 0, 0, 177, 143, 11, 252, 97, 5, 0, 0, 0, 9, 112, 72, 89, 115, 0, 0, 14,
@@ -0,0 +1,149 @@
+use std::fmt;
+
+use crate::chunk_type::ChunkType;
+
+/// The document that defines a chunk type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Specification {
+    Png12,
+    Png3,
+    Apng,
+    Extension,
+}
+
+impl fmt::Display for Specification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Specification::Png12 => "PNG 1.2",
+            Specification::Png3 => "PNG 3rd edition",
+            Specification::Apng => "APNG",
+            Specification::Extension => "registered extension",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A chunk type registered with the PNG group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownChunkType {
+    pub bytes: [u8; 4],
+    pub description: &'static str,
+    pub specification: Specification,
+}
+
+impl KnownChunkType {
+    const fn new(name: &[u8; 4], description: &'static str, specification: Specification) -> Self {
+        Self {
+            bytes: *name,
+            description,
+            specification,
+        }
+    }
+
+    pub fn chunk_type(&self) -> ChunkType {
+        ChunkType::try_from(self.bytes).expect("registry chunk types are valid")
+    }
+}
+
+use Specification::*;
+
+pub const KNOWN_CHUNK_TYPES: &[KnownChunkType] = &[
+    KnownChunkType::new(b"IHDR", "image header", Png12),
+    KnownChunkType::new(b"PLTE", "palette", Png12),
+    KnownChunkType::new(b"IDAT", "image data", Png12),
+    KnownChunkType::new(b"IEND", "image trailer", Png12),
+    KnownChunkType::new(b"bKGD", "background colour", Png12),
+    KnownChunkType::new(b"cHRM", "primary chromaticities and white point", Png12),
+    KnownChunkType::new(b"gAMA", "image gamma", Png12),
+    KnownChunkType::new(b"hIST", "image histogram", Png12),
+    KnownChunkType::new(b"iCCP", "embedded ICC profile", Png12),
+    KnownChunkType::new(b"iTXt", "international textual data", Png12),
+    KnownChunkType::new(b"pHYs", "physical pixel dimensions", Png12),
+    KnownChunkType::new(b"sBIT", "significant bits", Png12),
+    KnownChunkType::new(b"sPLT", "suggested palette", Png12),
+    KnownChunkType::new(b"sRGB", "standard RGB colour space", Png12),
+    KnownChunkType::new(b"tEXt", "textual data", Png12),
+    KnownChunkType::new(b"tIME", "image last-modification time", Png12),
+    KnownChunkType::new(b"tRNS", "transparency", Png12),
+    KnownChunkType::new(b"zTXt", "compressed textual data", Png12),
+    KnownChunkType::new(b"cICP", "coding-independent code points", Png3),
+    KnownChunkType::new(b"cLLI", "content light level information", Png3),
+    KnownChunkType::new(b"eXIf", "exchangeable image file profile", Png3),
+    KnownChunkType::new(b"mDCV", "mastering display colour volume", Png3),
+    KnownChunkType::new(b"acTL", "animation control", Apng),
+    KnownChunkType::new(b"fcTL", "frame control", Apng),
+    KnownChunkType::new(b"fdAT", "frame data", Apng),
+    KnownChunkType::new(b"oFFs", "image offset", Extension),
+    KnownChunkType::new(b"pCAL", "calibration of pixel values", Extension),
+    KnownChunkType::new(b"sCAL", "physical scale of image subject", Extension),
+    KnownChunkType::new(b"gIFg", "GIF graphic control extension", Extension),
+    KnownChunkType::new(b"gIFx", "GIF application extension", Extension),
+    KnownChunkType::new(b"gIFt", "GIF plain text extension", Extension),
+    KnownChunkType::new(b"sTER", "stereo image indicator", Extension),
+    KnownChunkType::new(b"dSIG", "digital signature", Extension),
+    KnownChunkType::new(b"fRAc", "fractal image parameters", Extension),
+];
+
+/// Returns the registry entry for `chunk_type`, if it is registered
+pub fn lookup(chunk_type: &ChunkType) -> Option<&'static KnownChunkType> {
+    KNOWN_CHUNK_TYPES
+        .iter()
+        .find(|known| known.bytes == chunk_type.bytes())
+}
+
+pub fn is_registered(chunk_type: &ChunkType) -> bool {
+    lookup(chunk_type).is_some()
+}
+
+/// Returns a one line human readable description of `chunk_type` followed by the document that
+/// defines it, e.g. `pHYs — physical pixel dimensions (ancillary, public, safe-to-copy) [PNG 1.2]`
+pub fn describe(chunk_type: &ChunkType) -> String {
+    match lookup(chunk_type) {
+        Some(known) => format!(
+            "{} — {} ({}) [{}]",
+            chunk_type,
+            known.description,
+            chunk_type.properties(),
+            known.specification
+        ),
+        None => format!(
+            "{} — unregistered ({})",
+            chunk_type,
+            chunk_type.properties()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_registry_entries_are_valid() {
+        for known in KNOWN_CHUNK_TYPES {
+            assert!(known.chunk_type().is_valid(), "{:?}", known);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let known = lookup(&ChunkType::from_str("pHYs").unwrap()).unwrap();
+        assert_eq!(known.description, "physical pixel dimensions");
+        assert_eq!(known.specification, Specification::Png12);
+
+        assert!(lookup(&ChunkType::from_str("ruSt").unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe(&ChunkType::from_str("pHYs").unwrap()),
+            "pHYs — physical pixel dimensions (ancillary, public, safe-to-copy) [PNG 1.2]"
+        );
+        assert_eq!(
+            describe(&ChunkType::from_str("RuSt").unwrap()),
+            "RuSt — unregistered (critical, private, safe-to-copy)"
+        );
+    }
+}
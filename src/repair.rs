@@ -0,0 +1,411 @@
+use std::fmt;
+
+use anyhow::bail;
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, IMAGE_END_CHUNK_TYPE};
+use crate::Result;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RepairOptions {
+    /// Keep chunks whose stored crc is wrong, writing the correct crc, instead of dropping them.
+    /// Without it a critical chunk with a wrong crc fails the repair, since the image cannot do
+    /// without it.
+    pub fix_crc: bool,
+}
+
+/// A single change made while repairing a file. Offsets refer to the damaged input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fix {
+    SignatureRestored,
+    CrcFixed {
+        offset: usize,
+        chunk_type: String,
+        stored: u32,
+        computed: u32,
+    },
+    ChunkDropped {
+        offset: usize,
+        chunk_type: String,
+        reason: String,
+    },
+    LengthFixed {
+        offset: usize,
+        chunk_type: String,
+        stored: usize,
+        actual: usize,
+    },
+    Resynchronised {
+        offset: usize,
+        skipped: usize,
+    },
+    TrailingBytesDropped {
+        offset: usize,
+        length: usize,
+    },
+    ImageEndAppended,
+}
+
+pub struct Repair {
+    pub png: Png,
+    pub fixes: Vec<Fix>,
+}
+
+/// A chunk header read at some offset, before its crc has been checked
+struct Candidate {
+    offset: usize,
+    length: usize,
+    chunk_type: ChunkType,
+}
+
+impl Candidate {
+    /// Reads a chunk header at `offset` if it has a valid type and fits within `bytes`
+    fn read(bytes: &[u8], offset: usize) -> Option<Self> {
+        let header =
+            bytes.get(offset..offset + Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES)?;
+        let length = u32::from_be_bytes(header[0..4].try_into().ok()?) as usize;
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..8]).ok()?).ok()?;
+        if !chunk_type.is_valid() {
+            return None;
+        }
+
+        let candidate = Self {
+            offset,
+            length,
+            chunk_type,
+        };
+        (candidate.end() <= bytes.len()).then_some(candidate)
+    }
+
+    fn data<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        let start = self.offset + Chunk::DATA_LENGTH_BYTES + Chunk::CHUNK_TYPE_BYTES;
+        &bytes[start..start + self.length]
+    }
+
+    fn stored_crc(&self, bytes: &[u8]) -> u32 {
+        let start = self.end() - Chunk::CRC_BYTES;
+        u32::from_be_bytes(bytes[start..self.end()].try_into().unwrap())
+    }
+
+    fn computed_crc(&self, bytes: &[u8]) -> u32 {
+        Chunk::checksum(&self.chunk_type, self.data(bytes))
+    }
+
+    fn is_intact(&self, bytes: &[u8]) -> bool {
+        self.stored_crc(bytes) == self.computed_crc(bytes)
+    }
+
+    fn end(&self) -> usize {
+        self.offset + Chunk::METADATA_BYTES + self.length
+    }
+
+    fn to_chunk(&self, bytes: &[u8]) -> Chunk {
+        Chunk::new(self.chunk_type.clone(), self.data(bytes).to_vec())
+    }
+}
+
+/// Finds the next offset after `from` holding a complete chunk with a matching crc
+fn resynchronise(bytes: &[u8], from: usize) -> Option<usize> {
+    (from..bytes.len()).find(|offset| {
+        Candidate::read(bytes, *offset).is_some_and(|candidate| candidate.is_intact(bytes))
+    })
+}
+
+/// Rebuilds the chunk at `offset` as ending right before `next`, for when only its length is
+/// damaged. Returns it if the type is valid and the crc then matches.
+fn refit(bytes: &[u8], offset: usize, next: usize) -> Option<Candidate> {
+    let type_bytes = bytes.get(offset + 4..offset + 8)?;
+    let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(type_bytes).ok()?).ok()?;
+    let candidate = Candidate {
+        offset,
+        length: next.checked_sub(offset + Chunk::METADATA_BYTES)?,
+        chunk_type,
+    };
+    (candidate.chunk_type.is_valid() && candidate.is_intact(bytes)).then_some(candidate)
+}
+
+/// Parses a damaged png as far as possible and returns the salvaged chunks with a log of every fix
+pub fn repair(bytes: &[u8], options: RepairOptions) -> Result<Repair> {
+    let mut fixes = Vec::new();
+    let header_length = Png::STANDARD_HEADER_LENGTH;
+
+    if bytes.len() < header_length + Chunk::METADATA_BYTES {
+        bail!("file is too short to contain a png")
+    }
+    if &bytes[..header_length] != Png::STANDARD_HEADER {
+        if &bytes[12..16] != b"IHDR" {
+            bail!("invalid png file header and no IHDR chunk follows it")
+        }
+        fixes.push(Fix::SignatureRestored);
+    }
+
+    let mut chunks = Vec::new();
+    let mut trailer = Vec::new();
+    let mut offset = header_length;
+    while offset < bytes.len() {
+        let candidate = Candidate::read(bytes, offset);
+
+        // a chunk that is intact, or whose only fault is its crc, ends where another chunk starts
+        let ends_cleanly = |candidate: &Candidate| {
+            candidate.end() == bytes.len() || Candidate::read(bytes, candidate.end()).is_some()
+        };
+
+        let candidate = match candidate {
+            Some(candidate) if candidate.is_intact(bytes) => candidate,
+            Some(candidate) if ends_cleanly(&candidate) => {
+                let (stored, computed) =
+                    (candidate.stored_crc(bytes), candidate.computed_crc(bytes));
+                if !options.fix_crc && candidate.chunk_type.is_critical() {
+                    bail!(
+                        "critical chunk [{}] at offset {} has crc {:08x} but its data has crc {:08x}; use --fix-crc to keep it with the correct crc",
+                        candidate.chunk_type,
+                        offset,
+                        stored,
+                        computed
+                    )
+                }
+                if options.fix_crc {
+                    fixes.push(Fix::CrcFixed {
+                        offset,
+                        chunk_type: candidate.chunk_type.to_string(),
+                        stored,
+                        computed,
+                    });
+                } else {
+                    fixes.push(Fix::ChunkDropped {
+                        offset,
+                        chunk_type: candidate.chunk_type.to_string(),
+                        reason: format!("stored crc {:08x} != computed {:08x}", stored, computed),
+                    });
+                    offset = candidate.end();
+                    continue;
+                }
+                candidate
+            }
+            _ => match resynchronise(bytes, offset + 1) {
+                Some(next) => {
+                    let stored = bytes
+                        .get(offset..offset + 4)
+                        .map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize);
+                    let repaired = refit(bytes, offset, next).zip(stored);
+
+                    match repaired {
+                        Some((candidate, stored)) => {
+                            fixes.push(Fix::LengthFixed {
+                                offset,
+                                chunk_type: candidate.chunk_type.to_string(),
+                                stored,
+                                actual: candidate.length,
+                            });
+                            candidate
+                        }
+                        None => {
+                            fixes.push(Fix::Resynchronised {
+                                offset,
+                                skipped: next - offset,
+                            });
+                            offset = next;
+                            continue;
+                        }
+                    }
+                }
+                None => {
+                    fixes.push(Fix::TrailingBytesDropped {
+                        offset,
+                        length: bytes.len() - offset,
+                    });
+                    break;
+                }
+            },
+        };
+
+        chunks.push(candidate.to_chunk(bytes));
+        offset = candidate.end();
+
+        if candidate.chunk_type.to_string() == IMAGE_END_CHUNK_TYPE {
+            trailer = bytes[offset..].to_vec();
+            break;
+        }
+    }
+
+    let has_end = chunks
+        .last()
+        .is_some_and(|chunk| chunk.chunk_type().to_string() == IMAGE_END_CHUNK_TYPE);
+    if !has_end {
+        let chunk_type: ChunkType = IMAGE_END_CHUNK_TYPE.parse()?;
+        chunks.push(Chunk::new(chunk_type, Vec::new()));
+        fixes.push(Fix::ImageEndAppended);
+    }
+
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailer(trailer);
+    Ok(Repair { png, fixes })
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fix::SignatureRestored => write!(f, "restored png signature"),
+            Fix::CrcFixed {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "{:08x}: [{}] crc {:08x} replaced with {:08x}",
+                offset, chunk_type, stored, computed
+            ),
+            Fix::ChunkDropped {
+                offset,
+                chunk_type,
+                reason,
+            } => write!(f, "{:08x}: dropped [{}], {}", offset, chunk_type, reason),
+            Fix::LengthFixed {
+                offset,
+                chunk_type,
+                stored,
+                actual,
+            } => write!(
+                f,
+                "{:08x}: [{}] length {} corrected to {}",
+                offset, chunk_type, stored, actual
+            ),
+            Fix::Resynchronised { offset, skipped } => write!(
+                f,
+                "{:08x}: skipped {} unreadable bytes to the next valid chunk",
+                offset, skipped
+            ),
+            Fix::TrailingBytesDropped { offset, length } => {
+                write!(f, "{:08x}: dropped {} trailing bytes", offset, length)
+            }
+            Fix::ImageEndAppended => write!(f, "appended missing IEND chunk"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_bytes() -> Vec<u8> {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", &[0x55; 40]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    fn types(repair: &Repair) -> Vec<String> {
+        repair
+            .png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    /// Offset of the tEXt chunk in `testing_bytes`
+    const TEXT_OFFSET: usize = 8 + 12 + 13;
+
+    #[test]
+    fn test_intact_file_unchanged() {
+        let bytes = testing_bytes();
+        let repair = repair(&bytes, RepairOptions::default()).unwrap();
+        assert!(repair.fixes.is_empty());
+        assert_eq!(repair.png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_bad_crc() {
+        let mut bytes = testing_bytes();
+        bytes[TEXT_OFFSET + 8] ^= 0xff;
+
+        let dropped = repair(&bytes, RepairOptions::default()).unwrap();
+        assert_eq!(types(&dropped), ["IHDR", "IDAT", "IEND"]);
+        assert!(matches!(dropped.fixes[..], [Fix::ChunkDropped { .. }]));
+
+        let fixed = repair(&bytes, RepairOptions { fix_crc: true }).unwrap();
+        assert_eq!(types(&fixed), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert!(matches!(fixed.fixes[..], [Fix::CrcFixed { .. }]));
+    }
+
+    #[test]
+    fn test_bad_crc_in_critical_chunk() {
+        let mut bytes = testing_bytes();
+        let idat_offset = TEXT_OFFSET + 12 + 13;
+        bytes[idat_offset + 8] ^= 0xff;
+
+        let error = repair(&bytes, RepairOptions::default()).err().unwrap();
+        assert!(error.to_string().contains("--fix-crc"), "{}", error);
+
+        let fixed = repair(&bytes, RepairOptions { fix_crc: true }).unwrap();
+        assert_eq!(types(&fixed), ["IHDR", "tEXt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = testing_bytes();
+        let repair = repair(&bytes[..bytes.len() - 30], RepairOptions::default()).unwrap();
+
+        assert_eq!(types(&repair), ["IHDR", "tEXt", "IEND"]);
+        assert!(matches!(
+            repair.fixes[..],
+            [Fix::TrailingBytesDropped { .. }, Fix::ImageEndAppended]
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_length_is_fixed() {
+        let mut bytes = testing_bytes();
+        bytes[TEXT_OFFSET..TEXT_OFFSET + 4].copy_from_slice(&[0, 0, 0, 3]);
+
+        let repair = repair(&bytes, RepairOptions::default()).unwrap();
+        assert_eq!(types(&repair), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(
+            repair.fixes,
+            [Fix::LengthFixed {
+                offset: TEXT_OFFSET,
+                chunk_type: "tEXt".to_string(),
+                stored: 3,
+                actual: 13,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_resynchronise_over_garbage() {
+        let mut bytes = testing_bytes();
+        bytes[TEXT_OFFSET..TEXT_OFFSET + 12].copy_from_slice(&[0xff; 12]);
+
+        let repair = repair(&bytes, RepairOptions::default()).unwrap();
+        assert_eq!(types(&repair), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(
+            repair.fixes,
+            [Fix::Resynchronised {
+                offset: TEXT_OFFSET,
+                skipped: 12 + 13,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_trailer_after_end_is_kept() {
+        let mut bytes = testing_bytes();
+        bytes.extend_from_slice(b"PK\x03\x04 trailing");
+
+        let repair = repair(&bytes, RepairOptions::default()).unwrap();
+        assert!(repair.fixes.is_empty());
+        assert_eq!(repair.png.trailer(), b"PK\x03\x04 trailing");
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::chunk::Chunk;
+use crate::chunk_data::ImageHeader;
+use crate::chunk_type::ChunkType;
+use crate::image::filtered_image_len;
+use crate::png::Png;
+use crate::registry;
+use crate::Result;
+
+pub const IMAGE_DATA_CHUNK_TYPE: &str = "IDAT";
+
+/// Chunks discarded while rewriting a png's image data
+#[derive(Default)]
+pub struct RewriteReport {
+    pub discarded: Vec<Chunk>,
+}
+
+/// Returns true if `chunk` must be dropped once the critical chunks of its png have been modified.
+/// Per the PNG spec, unknown ancillary chunks without the safe-to-copy bit may depend on the image data.
+pub fn is_unsafe_after_rewrite(chunk: &Chunk) -> bool {
+    let chunk_type = chunk.chunk_type();
+    !chunk_type.is_critical()
+        && !chunk_type.is_safe_to_copy()
+        && !registry::is_registered(chunk_type)
+}
+
+/// Returns the concatenated zlib stream stored across all IDAT chunks
+pub fn compressed_image_data(png: &Png) -> Vec<u8> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().to_string() == IMAGE_DATA_CHUNK_TYPE)
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect()
+}
+
+/// Error returned when a zlib stream inflates to more data than expected, such as image data
+/// that does not fit the dimensions in IHDR
+#[derive(Debug)]
+pub struct ExcessData {
+    pub limit: usize,
+}
+
+impl fmt::Display for ExcessData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "compressed data inflates to more than the {} bytes expected",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for ExcessData {}
+
+/// Inflates a zlib stream, failing with [`ExcessData`] instead of reading past `limit` bytes
+pub fn inflate(compressed: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() > limit {
+        return Err(ExcessData { limit }.into());
+    }
+    Ok(data)
+}
+
+/// Returns the decompressed (still filtered) image data of `png`, which may not be longer than
+/// its IHDR describes
+pub fn image_data(png: &Png) -> Result<Vec<u8>> {
+    let header = png
+        .chunk_by_type("IHDR")
+        .ok_or_else(|| anyhow!("png has no IHDR chunk"))?;
+    let header = ImageHeader::from_bytes(header.data())?;
+
+    let compressed = compressed_image_data(png);
+    if compressed.is_empty() {
+        bail!("png has no IDAT chunks")
+    }
+
+    inflate(&compressed, filtered_image_len(&header)?)
+}
+
+/// Compresses filtered image data into a zlib stream suitable for IDAT
+pub fn compress_image_data(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Replaces all IDAT chunks of `png` with a single IDAT holding `compressed` at the position of the first one.
+/// Unless `preserve_unsafe` is set, ancillary chunks that are not safe to copy are discarded.
+pub fn replace_image_data(
+    png: &mut Png,
+    compressed: Vec<u8>,
+    preserve_unsafe: bool,
+) -> Result<RewriteReport> {
+    let index = match png
+        .chunks()
+        .iter()
+        .position(|chunk| chunk.chunk_type().to_string() == IMAGE_DATA_CHUNK_TYPE)
+    {
+        Some(index) => index,
+        None => bail!("png has no IDAT chunks"),
+    };
+
+    png.remove_chunks_where(|chunk| chunk.chunk_type().to_string() == IMAGE_DATA_CHUNK_TYPE);
+    png.insert_chunk(
+        index,
+        Chunk::new(ChunkType::from_str(IMAGE_DATA_CHUNK_TYPE)?, compressed),
+    );
+
+    let mut report = RewriteReport::default();
+    if !preserve_unsafe {
+        report.discarded = png.remove_chunks_where(is_unsafe_after_rewrite);
+    }
+
+    Ok(report)
+}
+
+/// Re-encodes the image data of `png` at the given zlib compression level
+pub fn recompress(png: &mut Png, level: u32, preserve_unsafe: bool) -> Result<RewriteReport> {
+    let data = image_data(png)?;
+    let compressed = compress_image_data(&data, level)?;
+    replace_image_data(png, compressed, preserve_unsafe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn testing_png() -> Png {
+        let compressed = compress_image_data(&[0, 1, 2, 3, 0, 4, 5, 6], 6).unwrap();
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 3, 0, 0, 0, 2, 8, 0, 0, 0, 0]),
+            chunk("tEXt", b"safe"),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("IDAT", first),
+            chunk("IDAT", second),
+            chunk("prVT", b"unsafe"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_image_data_spans_chunks() {
+        let png = testing_png();
+        assert_eq!(image_data(&png).unwrap(), vec![0, 1, 2, 3, 0, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_excess_image_data() {
+        let mut png = testing_png();
+        let compressed = compress_image_data(&[0; 1024 * 1024], 9).unwrap();
+        replace_image_data(&mut png, compressed, true).unwrap();
+
+        let error = image_data(&png).unwrap_err();
+        assert_eq!(error.downcast_ref::<ExcessData>().unwrap().limit, 8);
+    }
+
+    #[test]
+    fn test_recompress_discards_unsafe_chunks() {
+        let mut png = testing_png();
+        let report = recompress(&mut png, 9, false).unwrap();
+
+        assert_eq!(report.discarded.len(), 1);
+        assert_eq!(report.discarded[0].chunk_type().to_string(), "prVT");
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "tEXt", "gAMA", "IDAT", "IEND"]);
+        assert_eq!(image_data(&png).unwrap(), vec![0, 1, 2, 3, 0, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_recompress_preserves_unsafe_chunks() {
+        let mut png = testing_png();
+        let report = recompress(&mut png, 9, true).unwrap();
+
+        assert!(report.discarded.is_empty());
+        assert!(png.chunk_by_type("prVT").is_some());
+    }
+}
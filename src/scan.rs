@@ -0,0 +1,587 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::chunk_data::ChunkData;
+use crate::chunk_type::ChunkType;
+use crate::image::{Image, INDEXED};
+use crate::png::{ChunkLayout, Png};
+use crate::rewrite::ExcessData;
+use crate::{registry, trailer};
+
+/// Ancillary chunks larger than this are reported
+pub const LARGE_ANCILLARY_LENGTH: usize = 64 * 1024;
+
+/// Text with more bits of entropy per byte than this looks like encoded or encrypted data
+pub const HIGH_ENTROPY_BITS: f64 = 5.0;
+
+/// Text shorter than this is too short for a meaningful entropy estimate
+pub const MIN_ENTROPY_LENGTH: usize = 32;
+
+/// An RS estimate of more than this fraction of samples carrying message bits is reported
+pub const LSB_RATE_THRESHOLD: f64 = 0.3;
+
+/// RS analysis needs at least this many samples
+pub const MIN_LSB_SAMPLES: usize = 1024;
+
+/// Flipping mask applied to each group of adjacent samples in RS analysis
+const RS_MASK: [bool; 4] = [false, true, true, false];
+
+/// Signatures of other formats that make a png a polyglot when found inside it
+pub const POLYGLOT_MARKERS: &[(&[u8], &str)] = &[
+    (b"<html", "html"),
+    (b"<script", "javascript"),
+    (b"<?php", "php"),
+    (b"<svg", "svg"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Indicator {
+    Malformed,
+    PrivateChunk,
+    UnknownChunk,
+    ChunksAfterEnd,
+    TrailerData,
+    LargeAncillaryChunk,
+    HighEntropyText,
+    LsbAnomaly,
+    PolyglotSignature,
+    ExcessImageData,
+}
+
+impl Indicator {
+    /// Points each finding of this indicator adds to a file's score
+    pub fn weight(&self) -> u32 {
+        match self {
+            Indicator::Malformed => 10,
+            Indicator::PrivateChunk => 20,
+            Indicator::UnknownChunk => 15,
+            Indicator::ChunksAfterEnd => 40,
+            Indicator::TrailerData => 30,
+            Indicator::LargeAncillaryChunk => 15,
+            Indicator::HighEntropyText => 25,
+            Indicator::LsbAnomaly => 35,
+            Indicator::PolyglotSignature => 30,
+            Indicator::ExcessImageData => 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Clean,
+    Suspicious,
+    Likely,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub indicator: Indicator,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScanReport {
+    /// Sum of the finding weights, capped at 100
+    pub score: u32,
+    pub verdict: Verdict,
+    pub findings: Vec<Finding>,
+}
+
+impl ScanReport {
+    fn new(findings: Vec<Finding>) -> Self {
+        let score = findings
+            .iter()
+            .map(|finding| finding.indicator.weight())
+            .sum::<u32>()
+            .min(100);
+        let verdict = match score {
+            0..=19 => Verdict::Clean,
+            20..=59 => Verdict::Suspicious,
+            _ => Verdict::Likely,
+        };
+
+        Self {
+            score,
+            verdict,
+            findings,
+        }
+    }
+}
+
+/// Looks for signs of hidden data in a png file
+pub fn scan(bytes: &[u8]) -> ScanReport {
+    let mut findings = Vec::new();
+    let finding = |indicator, detail: String| Finding { indicator, detail };
+
+    let layout = match Png::layout(bytes) {
+        Ok(layout) => layout,
+        Err(error) => {
+            findings.push(finding(Indicator::Malformed, error.to_string()));
+            findings.extend(polyglot_findings(bytes));
+            return ScanReport::new(findings);
+        }
+    };
+
+    for chunk in &layout {
+        findings.extend(chunk_findings(bytes, chunk));
+    }
+
+    let trailer_offset = Png::trailer_offset(&layout);
+    let trailer = &bytes[trailer_offset..];
+    if !trailer.is_empty() {
+        let chunks_after_end = chunk_types_after_end(trailer);
+        if chunks_after_end.is_empty() {
+            findings.push(finding(
+                Indicator::TrailerData,
+                format!(
+                    "{} bytes after IEND at offset {} ({})",
+                    trailer.len(),
+                    trailer_offset,
+                    trailer::identify(trailer).unwrap_or("unknown format")
+                ),
+            ));
+        } else {
+            findings.push(finding(
+                Indicator::ChunksAfterEnd,
+                format!("chunks after IEND: {}", chunks_after_end.join(", ")),
+            ));
+        }
+    }
+
+    findings.extend(polyglot_findings(bytes));
+
+    match Png::try_from(bytes).and_then(|png| Image::decode(&png)) {
+        Ok(image) => findings.extend(lsb_finding(&image)),
+        Err(error) => match error.downcast_ref::<ExcessData>() {
+            Some(excess) => findings.push(finding(
+                Indicator::ExcessImageData,
+                format!(
+                    "IDAT inflates to more than the {} bytes IHDR describes",
+                    excess.limit
+                ),
+            )),
+            None => findings.push(finding(
+                Indicator::Malformed,
+                format!("image does not decode: {}", error),
+            )),
+        },
+    }
+
+    ScanReport::new(findings)
+}
+
+fn chunk_findings(bytes: &[u8], chunk: &ChunkLayout) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let chunk_type = &chunk.chunk_type;
+    let name = format!("chunk {} [{}]", chunk.index, chunk_type);
+
+    if !chunk_type.is_public() {
+        findings.push(Finding {
+            indicator: Indicator::PrivateChunk,
+            detail: format!("{} is a private chunk type", name),
+        });
+    } else if !registry::is_registered(chunk_type) {
+        findings.push(Finding {
+            indicator: Indicator::UnknownChunk,
+            detail: format!("{} is not a registered chunk type", name),
+        });
+    }
+
+    if !chunk_type.is_critical() && chunk.length > LARGE_ANCILLARY_LENGTH {
+        findings.push(Finding {
+            indicator: Indicator::LargeAncillaryChunk,
+            detail: format!("{} holds {} bytes", name, chunk.length),
+        });
+    }
+
+    let text = match ChunkData::parse_bytes(&chunk_type.bytes(), chunk.data(bytes)) {
+        Ok(Some(ChunkData::Text { text, .. }))
+        | Ok(Some(ChunkData::CompressedText { text, .. }))
+        | Ok(Some(ChunkData::InternationalText { text, .. })) => Some(text),
+        _ => None,
+    };
+    if let Some(text) = text.filter(|text| text.len() >= MIN_ENTROPY_LENGTH) {
+        let entropy = entropy(text.as_bytes());
+        if entropy > HIGH_ENTROPY_BITS {
+            findings.push(Finding {
+                indicator: Indicator::HighEntropyText,
+                detail: format!("{} text has {:.2} bits of entropy per byte", name, entropy),
+            });
+        }
+    }
+
+    findings
+}
+
+/// Types of the well-formed chunk headers at the start of `trailer`
+fn chunk_types_after_end(trailer: &[u8]) -> Vec<String> {
+    let mut types = Vec::new();
+    let mut offset = 0;
+    while let Some(header) = trailer.get(offset..offset + 8) {
+        let length = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        match ChunkType::try_from(<[u8; 4]>::try_from(&header[4..8]).unwrap()) {
+            Ok(chunk_type) if chunk_type.is_valid() => types.push(chunk_type.to_string()),
+            _ => break,
+        }
+        offset += length + 12;
+    }
+    types
+}
+
+/// Signatures of other file formats anywhere after the png signature
+fn polyglot_findings(bytes: &[u8]) -> Vec<Finding> {
+    trailer::MAGIC_NUMBERS
+        .iter()
+        .chain(POLYGLOT_MARKERS)
+        .filter(|(magic, _)| magic.len() >= 4)
+        .filter_map(|(magic, name)| {
+            let offset = bytes
+                .get(1..)
+                .unwrap_or_default()
+                .windows(magic.len())
+                .position(|window| window == *magic)?
+                + 1;
+            Some(Finding {
+                indicator: Indicator::PolyglotSignature,
+                detail: format!("{} signature at offset {}", name, offset),
+            })
+        })
+        .collect()
+}
+
+fn lsb_finding(image: &Image) -> Option<Finding> {
+    if image.bit_depth != 8 || image.color_type == INDEXED {
+        return None;
+    }
+    let samples = image.samples();
+    if samples.len() < MIN_LSB_SAMPLES {
+        return None;
+    }
+
+    let rate = rs_lsb_rate(&samples, image.width as usize, image.channels())?;
+    (rate > LSB_RATE_THRESHOLD).then(|| Finding {
+        indicator: Indicator::LsbAnomaly,
+        detail: format!(
+            "RS analysis estimates {:.0}% of sample least significant bits carry data",
+            rate * 100.0
+        ),
+    })
+}
+
+/// Shannon entropy of `data` in bits per byte
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    data.iter().for_each(|byte| counts[*byte as usize] += 1);
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / data.len() as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Fridrich, Goljan and Du's RS analysis of 8-bit samples, laid out `width` pixels of
+/// `channels` samples per row. Groups of adjacent samples in one channel are classed as regular
+/// or singular by whether flipping their least significant bits makes them less or more smooth.
+/// Replacing those bits with message bits moves the two classes together, and comparing the
+/// classes before and after flipping every bit estimates the fraction of samples that carry a
+/// message. Unlike a chi-square test on the histogram, a clean image with noise gives an estimate
+/// near 0. Returns None if there are too few groups to classify.
+pub fn rs_lsb_rate(samples: &[u16], width: usize, channels: usize) -> Option<f64> {
+    let row_length = width * channels;
+    if row_length == 0 {
+        return None;
+    }
+    let rows: Vec<&[u16]> = samples.chunks_exact(row_length).collect();
+    let at = |x: usize, y: usize, channel: usize| rows[y][x * channels + channel] as i32;
+
+    // regular minus singular groups for each flipping before and after flipping every bit,
+    // over overlapping runs of adjacent samples along rows and along columns
+    let mut totals = [0i64; 4];
+    let mut groups = 0usize;
+    let mut classify = |group: [i32; 4]| {
+        let flipped = group.map(flip);
+        totals[0] += regular_or_singular(&group, flip);
+        totals[1] += regular_or_singular(&group, shift);
+        totals[2] += regular_or_singular(&flipped, flip);
+        totals[3] += regular_or_singular(&flipped, shift);
+        groups += 1;
+    };
+    for channel in 0..channels {
+        for y in 0..rows.len() {
+            for x in 0..width.saturating_sub(3) {
+                classify([0, 1, 2, 3].map(|i| at(x + i, y, channel)));
+            }
+        }
+        for x in 0..width {
+            for y in 0..rows.len().saturating_sub(3) {
+                classify([0, 1, 2, 3].map(|i| at(x, y + i, channel)));
+            }
+        }
+    }
+    if groups == 0 {
+        return None;
+    }
+    let [d0, d_minus_0, d1, d_minus_1] = totals.map(|total| total as f64 / groups as f64);
+
+    // the differences are quadratic in the embedding rate; the root nearest 0 gives the estimate
+    let a = 2.0 * (d1 + d0);
+    let b = d_minus_0 - d_minus_1 - d1 - 3.0 * d0;
+    let c = d0 - d_minus_0;
+    let x = if a.abs() < f64::EPSILON {
+        if b.abs() < f64::EPSILON {
+            return Some(0.0);
+        }
+        -c / b
+    } else {
+        let root = (b * b - 4.0 * a * c).max(0.0).sqrt();
+        [(-b + root) / (2.0 * a), (-b - root) / (2.0 * a)]
+            .into_iter()
+            .min_by(|x, y| x.abs().total_cmp(&y.abs()))?
+    };
+
+    Some((x / (x - 0.5)).abs().min(1.0))
+}
+
+/// Flips the least significant bit, swapping 2k and 2k+1
+fn flip(sample: i32) -> i32 {
+    sample ^ 1
+}
+
+/// Flips the least significant bit the other way, swapping 2k-1 and 2k
+fn shift(sample: i32) -> i32 {
+    flip(sample + 1) - 1
+}
+
+fn smoothness(group: &[i32; 4]) -> i32 {
+    group.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum()
+}
+
+/// 1 if applying `operation` to the masked samples of `group` makes it less smooth (a regular
+/// group), -1 if it makes it smoother (a singular group), 0 otherwise
+fn regular_or_singular(group: &[i32; 4], operation: fn(i32) -> i32) -> i64 {
+    let mut changed = *group;
+    changed
+        .iter_mut()
+        .zip(RS_MASK)
+        .filter(|(_, masked)| *masked)
+        .for_each(|(sample, _)| *sample = operation(*sample));
+    match smoothness(&changed).cmp(&smoothness(group)) {
+        std::cmp::Ordering::Greater => 1,
+        std::cmp::Ordering::Less => -1,
+        std::cmp::Ordering::Equal => 0,
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = match self.verdict {
+            Verdict::Clean => "clean",
+            Verdict::Suspicious => "suspicious",
+            Verdict::Likely => "hidden data likely",
+        };
+        write!(f, "score {}/100: {}", self.score, verdict)?;
+        self.findings.iter().try_for_each(|finding| {
+            write!(
+                f,
+                "\n  [{:>2}] {:?}: {}",
+                finding.indicator.weight(),
+                finding.indicator,
+                finding.detail
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::image::GRAYSCALE;
+    use crate::rewrite::{compress_image_data, replace_image_data};
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// A 64x64 gradient that only uses even sample values, like an image with untouched LSBs
+    fn clean_image() -> Image {
+        let data = (0..64 * 64).map(|i| ((i % 64) * 4) as u8).collect();
+        Image::new(64, 64, GRAYSCALE, 8, data).unwrap()
+    }
+
+    fn indicators(report: &ScanReport) -> Vec<Indicator> {
+        report.findings.iter().map(|f| f.indicator).collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        let bytes = clean_image().to_png(6).unwrap().as_bytes();
+        let report = scan(&bytes);
+        assert!(report.findings.is_empty(), "{}", report);
+        assert_eq!(report.verdict, Verdict::Clean);
+    }
+
+    #[test]
+    fn test_chunk_indicators() {
+        let mut png = clean_image().to_png(6).unwrap();
+        png.insert_chunk(1, chunk("ruSt", b"hidden"));
+        png.insert_chunk(1, chunk("aBCD", b"unknown"));
+        let encoded: String = (0..200u32)
+            .map(|i| {
+                char::from(
+                    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+                        [(i * 37 % 64) as usize],
+                )
+            })
+            .collect();
+        png.insert_chunk(1, chunk("tEXt", format!("Comment\0{}", encoded).as_bytes()));
+
+        let report = scan(&png.as_bytes());
+        assert_eq!(
+            indicators(&report),
+            [
+                Indicator::HighEntropyText,
+                Indicator::UnknownChunk,
+                Indicator::PrivateChunk
+            ]
+        );
+        assert_eq!(report.score, 60);
+        assert_eq!(report.verdict, Verdict::Likely);
+    }
+
+    #[test]
+    fn test_trailer_and_polyglot() {
+        let mut png = clean_image().to_png(6).unwrap();
+        png.set_trailer(b"PK\x03\x04rest of a zip".to_vec());
+
+        let report = scan(&png.as_bytes());
+        assert_eq!(
+            indicators(&report),
+            [Indicator::TrailerData, Indicator::PolyglotSignature]
+        );
+        assert!(report.findings[0].detail.contains("zip"));
+    }
+
+    #[test]
+    fn test_empty_and_signature_only() {
+        for bytes in [
+            &b""[..],
+            &Png::STANDARD_HEADER[..],
+            &Png::STANDARD_HEADER[..3],
+        ] {
+            let report = scan(bytes);
+            assert_eq!(report.findings[0].indicator, Indicator::Malformed);
+        }
+    }
+
+    #[test]
+    fn test_chunks_after_end() {
+        let mut png = clean_image().to_png(6).unwrap();
+        png.set_trailer(chunk("ruSt", b"after the end").as_bytes());
+
+        let report = scan(&png.as_bytes());
+        assert_eq!(indicators(&report), [Indicator::ChunksAfterEnd]);
+    }
+
+    #[test]
+    fn test_excess_image_data() {
+        let mut png = clean_image().to_png(6).unwrap();
+        let compressed = compress_image_data(&[0; 1024 * 1024], 9).unwrap();
+        replace_image_data(&mut png, compressed, true).unwrap();
+
+        let report = scan(&png.as_bytes());
+        assert_eq!(indicators(&report), [Indicator::ExcessImageData]);
+        assert_eq!(report.verdict, Verdict::Suspicious);
+    }
+
+    /// Deterministic xorshift generator for test noise and message bits
+    struct Noise(u64);
+
+    impl Noise {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn uniform(&mut self) -> f64 {
+            (self.next() >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        /// Normally distributed sample, by the Box-Muller transform
+        fn gaussian(&mut self, sigma: f64) -> f64 {
+            let (u, v) = (1.0 - self.uniform(), self.uniform());
+            sigma * (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+        }
+    }
+
+    /// A 128x128 RGB gradient with gaussian noise, like a photograph with untouched LSBs
+    fn noisy_image(sigma: f64) -> Image {
+        let mut noise = Noise(0x2545f4914f6cdd1d);
+        let data = (0..128 * 128 * 3)
+            .map(|i| {
+                let (x, y, channel) = (i / 3 % 128, i / 3 / 128, i % 3);
+                let value = (x + y + channel * 20) as f64 * 0.8 + 20.0 + noise.gaussian(sigma);
+                value.round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        Image::new(128, 128, crate::image::TRUECOLOR, 8, data).unwrap()
+    }
+
+    /// Replaces the least significant bit of a `rate` fraction of samples with random bits
+    fn embed_lsb(image: &Image, rate: f64) -> Image {
+        let mut noise = Noise(0x9e3779b97f4a7c15);
+        let data = image
+            .data
+            .iter()
+            .map(|sample| match noise.uniform() < rate {
+                true => sample & !1 | (noise.next() & 1) as u8,
+                false => *sample,
+            })
+            .collect();
+        Image {
+            data,
+            ..image.clone()
+        }
+    }
+
+    fn lsb_rate(image: &Image) -> f64 {
+        rs_lsb_rate(&image.samples(), image.width as usize, image.channels()).unwrap()
+    }
+
+    #[test]
+    fn test_noisy_image_is_clean() {
+        let image = noisy_image(6.0);
+        assert!(lsb_rate(&image) < LSB_RATE_THRESHOLD);
+
+        let report = scan(&image.to_png(6).unwrap().as_bytes());
+        assert!(report.findings.is_empty(), "{}", report);
+        assert_eq!(report.verdict, Verdict::Clean);
+    }
+
+    #[test]
+    fn test_lsb_embedding() {
+        let image = noisy_image(6.0);
+        assert!((lsb_rate(&embed_lsb(&image, 0.5)) - 0.5).abs() < 0.1);
+
+        let stego = embed_lsb(&image, 1.0);
+        assert!(lsb_rate(&stego) > 0.8);
+
+        let report = scan(&stego.to_png(6).unwrap().as_bytes());
+        assert_eq!(indicators(&report), [Indicator::LsbAnomaly]);
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(b"aaaa"), 0.0);
+        assert_eq!(entropy(b"abab"), 1.0);
+    }
+}
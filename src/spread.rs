@@ -0,0 +1,228 @@
+use anyhow::{anyhow, bail};
+
+use crate::apng::Apng;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::Result;
+
+/// Bytes before each part's data: its index and the number of parts, both big endian
+pub const PART_HEADER_BYTES: usize = 8;
+
+/// Hides `payload` in an animated png by splitting it into one chunk of `chunk_type` per frame,
+/// placed after the frame's image data. Only fcTL and fdAT carry sequence numbers, so the
+/// animation is unchanged; this is checked by parsing the result and comparing its frames and
+/// chunk order with the original.
+pub fn embed(png: &Png, chunk_type: &ChunkType, payload: &[u8]) -> Result<Png> {
+    if chunk_type.is_critical() {
+        bail!(
+            "[{}] is critical, players would refuse the animation",
+            chunk_type
+        )
+    }
+    if png
+        .chunks()
+        .iter()
+        .any(|chunk| chunk.chunk_type() == chunk_type)
+    {
+        bail!("png already carries [{}] chunks", chunk_type)
+    }
+
+    let apng = Apng::new(png)?;
+    let frames = apng.frames();
+    let part_length = payload.len().div_ceil(frames.len());
+
+    let mut chunks: Vec<Chunk> = png
+        .chunks()
+        .iter()
+        .map(|chunk| Chunk::new(chunk.chunk_type().clone(), chunk.data().to_vec()))
+        .collect();
+    // insert from the last frame so earlier chunk indices stay valid
+    for frame in frames.iter().rev() {
+        let start = (frame.index * part_length).min(payload.len());
+        let end = (start + part_length).min(payload.len());
+
+        let data = (frame.index as u32)
+            .to_be_bytes()
+            .iter()
+            .chain((frames.len() as u32).to_be_bytes().iter())
+            .chain(&payload[start..end])
+            .copied()
+            .collect();
+        let after = frame.data_chunks.last().unwrap() + 1;
+        chunks.insert(after, Chunk::new(chunk_type.clone(), data));
+    }
+
+    let mut embedded = Png::from_chunks(chunks);
+    embedded.set_trailer(png.trailer().to_vec());
+
+    check_embedded(&apng, &embedded, chunk_type)?;
+    Ok(embedded)
+}
+
+/// Checks that `embedded` is `original` with one part chunk after each frame's image data: the
+/// animation chunks still parse with consecutive sequence numbers, and every other chunk is
+/// unchanged and in its original order
+fn check_embedded(original: &Apng, embedded: &Png, chunk_type: &ChunkType) -> Result<()> {
+    let apng = Apng::new(embedded)?;
+
+    let unchanged = original
+        .frames()
+        .iter()
+        .zip(apng.frames())
+        .all(|(before, after)| before.control == after.control);
+    if !unchanged || original.frames().len() != apng.frames().len() {
+        bail!("the animation frames changed while embedding the payload")
+    }
+    for frame in apng.frames() {
+        let next = embedded.chunks().get(frame.data_chunks.last().unwrap() + 1);
+        if next.map(Chunk::chunk_type) != Some(chunk_type) {
+            bail!(
+                "frame {} is not followed by its [{}] part",
+                frame.index,
+                chunk_type
+            )
+        }
+    }
+
+    let mut kept = embedded
+        .chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type() != chunk_type);
+    let in_order = original.png().chunks().iter().all(|chunk| {
+        kept.next().is_some_and(|kept| {
+            kept.chunk_type() == chunk.chunk_type() && kept.data() == chunk.data()
+        })
+    });
+    if !in_order || kept.next().is_some() {
+        bail!("the chunk order changed while embedding the payload")
+    }
+    Ok(())
+}
+
+/// Reassembles a payload hidden by [`embed`]
+pub fn extract(png: &Png, chunk_type: &ChunkType) -> Result<Vec<u8>> {
+    let mut parts = Vec::new();
+    for chunk in png.chunks() {
+        if chunk.chunk_type() != chunk_type {
+            continue;
+        }
+        let data = chunk.data();
+        if data.len() < PART_HEADER_BYTES {
+            bail!("[{}] chunk is shorter than its part header", chunk_type)
+        }
+        let index = u32::from_be_bytes(data[0..4].try_into()?) as usize;
+        let count = u32::from_be_bytes(data[4..8].try_into()?) as usize;
+        parts.push((index, count, &data[PART_HEADER_BYTES..]));
+    }
+
+    let count = parts
+        .first()
+        .map(|(_, count, _)| *count)
+        .ok_or(anyhow!("png carries no [{}] chunks", chunk_type))?;
+    parts.sort_by_key(|(index, _, _)| *index);
+    if parts.len() != count
+        || parts
+            .iter()
+            .enumerate()
+            .any(|(position, (index, part_count, _))| *index != position || *part_count != count)
+    {
+        bail!(
+            "expected {} [{}] parts numbered from 0, found {}",
+            count,
+            chunk_type,
+            parts.len()
+        )
+    }
+
+    Ok(parts
+        .into_iter()
+        .flat_map(|(_, _, data)| data)
+        .copied()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::assemble;
+    use crate::image::{Image, TRUECOLOR_ALPHA};
+    use std::str::FromStr;
+
+    fn testing_apng() -> Png {
+        let images: Vec<Image> = (0..3)
+            .map(|frame| {
+                let data = [frame * 80, 0, 255 - frame * 80, 255].repeat(6);
+                Image::new(3, 2, TRUECOLOR_ALPHA, 8, data).unwrap()
+            })
+            .collect();
+        assemble(&images, &[100; 3], 0, 6).unwrap()
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let png = testing_apng();
+        let chunk_type = ChunkType::from_str("prTs").unwrap();
+        let payload = b"spread over every frame of the animation";
+
+        let embedded = embed(&png, &chunk_type, payload).unwrap();
+        let bytes = embedded.as_bytes();
+        let parsed = Png::try_from(bytes.as_slice()).unwrap();
+
+        let apng = Apng::new(&parsed).unwrap();
+        for frame in apng.frames() {
+            let next = &parsed.chunks()[frame.data_chunks.last().unwrap() + 1];
+            assert_eq!(next.chunk_type(), &chunk_type);
+        }
+        assert_eq!(extract(&parsed, &chunk_type).unwrap(), payload);
+
+        assert!(embed(&embedded, &chunk_type, payload).is_err());
+        assert!(embed(&png, &ChunkType::from_str("PrTs").unwrap(), payload).is_err());
+    }
+
+    #[test]
+    fn test_check_embedded() {
+        let png = testing_apng();
+        let apng = Apng::new(&png).unwrap();
+        let chunk_type = ChunkType::from_str("prTs").unwrap();
+        let embedded = embed(&png, &chunk_type, b"payload").unwrap();
+        assert!(check_embedded(&apng, &embedded, &chunk_type).is_ok());
+
+        // a part moved away from its frame
+        let mut moved = Png::try_from(embedded.as_bytes().as_slice()).unwrap();
+        let part = moved.remove_chunk("prTs").unwrap();
+        moved.append_chunk(part);
+        assert!(check_embedded(&apng, &moved, &chunk_type).is_err());
+
+        // an original chunk dropped
+        let mut dropped = Png::try_from(embedded.as_bytes().as_slice()).unwrap();
+        dropped.remove_chunk("IEND").unwrap();
+        assert!(check_embedded(&apng, &dropped, &chunk_type).is_err());
+    }
+
+    #[test]
+    fn test_short_payload() {
+        let png = testing_apng();
+        let chunk_type = ChunkType::from_str("prTs").unwrap();
+
+        let embedded = embed(&png, &chunk_type, b"x").unwrap();
+        assert_eq!(extract(&embedded, &chunk_type).unwrap(), b"x");
+    }
+
+    #[test]
+    fn test_missing_part() {
+        let png = testing_apng();
+        let chunk_type = ChunkType::from_str("prTs").unwrap();
+        let mut embedded = embed(&png, &chunk_type, b"payload").unwrap();
+
+        let mut seen = 0;
+        embedded.remove_chunks_where(|chunk| {
+            chunk.chunk_type() == &chunk_type && {
+                seen += 1;
+                seen == 2
+            }
+        });
+        assert!(extract(&embedded, &chunk_type).is_err());
+        assert!(extract(&png, &chunk_type).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// Decides which chunks are dropped when stripping metadata from a png.
+/// Chunk types listed in `keep` are always retained.
+#[derive(Debug, Default, Clone)]
+pub struct StripPolicy {
+    pub keep_critical_only: bool,
+    pub remove_private: bool,
+    pub keep: Vec<ChunkType>,
+}
+
+impl StripPolicy {
+    /// Returns true if a chunk of the given type should be removed under this policy
+    pub fn should_remove(&self, chunk_type: &ChunkType) -> bool {
+        if self.keep.contains(chunk_type) {
+            return false;
+        }
+
+        if self.remove_private && !chunk_type.is_public() {
+            return true;
+        }
+
+        self.keep_critical_only && !chunk_type.is_critical()
+    }
+
+    /// Returns the chunks of `png` that would be removed, without modifying it
+    pub fn matching<'a>(&self, png: &'a Png) -> Vec<&'a Chunk> {
+        png.chunks()
+            .iter()
+            .filter(|chunk| self.should_remove(chunk.chunk_type()))
+            .collect()
+    }
+
+    /// Removes every chunk matched by this policy and returns them in file order
+    pub fn apply(&self, png: &mut Png) -> Vec<Chunk> {
+        png.remove_chunks_where(|chunk| self.should_remove(chunk.chunk_type()))
+    }
+}
+
+/// Number of bytes the given chunks occupy in a png file, including length, type and crc
+pub fn encoded_size<'a>(chunks: impl IntoIterator<Item = &'a Chunk>) -> usize {
+    chunks
+        .into_iter()
+        .map(|chunk| chunk.length() + Chunk::METADATA_BYTES)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), b"data".to_vec())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR"),
+            chunk("sRGB"),
+            chunk("tEXt"),
+            chunk("ruSt"),
+            chunk("IDAT"),
+            chunk("IEND"),
+        ])
+    }
+
+    fn types(chunks: &[&Chunk]) -> Vec<String> {
+        chunks.iter().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_keep_critical_only() {
+        let policy = StripPolicy {
+            keep_critical_only: true,
+            ..Default::default()
+        };
+        let png = testing_png();
+        assert_eq!(types(&policy.matching(&png)), ["sRGB", "tEXt", "ruSt"]);
+    }
+
+    #[test]
+    fn test_keep_list_overrides() {
+        let policy = StripPolicy {
+            keep_critical_only: true,
+            keep: vec![ChunkType::from_str("sRGB").unwrap()],
+            ..Default::default()
+        };
+        let png = testing_png();
+        assert_eq!(types(&policy.matching(&png)), ["tEXt", "ruSt"]);
+    }
+
+    #[test]
+    fn test_remove_private() {
+        let policy = StripPolicy {
+            remove_private: true,
+            ..Default::default()
+        };
+        let png = testing_png();
+        assert_eq!(types(&policy.matching(&png)), ["ruSt"]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = StripPolicy {
+            keep_critical_only: true,
+            ..Default::default()
+        };
+        let mut png = testing_png();
+        let removed = policy.apply(&mut png);
+        assert_eq!(removed.len(), 3);
+        assert_eq!(encoded_size(&removed), 3 * (4 + Chunk::METADATA_BYTES));
+        assert_eq!(png.chunks().len(), 3);
+    }
+}
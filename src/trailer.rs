@@ -0,0 +1,73 @@
+use crate::chunk::Chunk;
+
+/// File signatures commonly found appended after IEND
+pub const MAGIC_NUMBERS: &[(&[u8], &str)] = &[
+    (b"PK\x03\x04", "zip"),
+    (b"PK\x05\x06", "zip (empty)"),
+    (b"Rar!\x1a\x07", "rar"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"\x1f\x8b", "gzip"),
+    (b"BZh", "bzip2"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"%PDF", "pdf"),
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpeg"),
+    (b"GIF8", "gif"),
+    (b"RIFF", "riff"),
+    (b"\x7fELF", "elf"),
+    (b"MZ", "windows executable"),
+    (b"-----BEGIN PGP", "pgp armored data"),
+];
+
+/// Names the format of `data` from its leading bytes, if recognised
+pub fn identify(data: &[u8]) -> Option<&'static str> {
+    MAGIC_NUMBERS
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, name)| *name)
+}
+
+/// Chunks at the start of a trailer, as written after IEND by earlier versions of pngme that
+/// appended new chunks to the end of the file. Reading stops at the first bytes that are not a
+/// chunk with a valid crc.
+pub fn legacy_chunks(trailer: &[u8]) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while let Ok(chunk) = Chunk::try_from(&trailer[offset..]) {
+        offset += chunk.length() + Chunk::METADATA_BYTES;
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(identify(b"PK\x03\x04\x14\x00"), Some("zip"));
+        assert_eq!(identify(b"%PDF-1.7"), Some("pdf"));
+        assert_eq!(identify(b"hello"), None);
+        assert_eq!(identify(b""), None);
+    }
+
+    #[test]
+    fn test_legacy_chunks() {
+        let chunk = |chunk_type: &str, data: &[u8]| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+        };
+        let mut trailer = chunk("ruSt", b"first").as_bytes();
+        trailer.extend(chunk("ruSt", b"second").as_bytes());
+        trailer.extend_from_slice(b"PK\x03\x04");
+
+        let chunks = legacy_chunks(&trailer);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].data(), b"second");
+
+        assert!(legacy_chunks(b"PK\x03\x04").is_empty());
+        assert!(legacy_chunks(b"").is_empty());
+    }
+}
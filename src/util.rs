@@ -1,3 +1,31 @@
-pub fn is_set(byte: u8, pos: u8) -> bool {
-    byte & pos != pos
+/// Returns true if every bit of `mask` is set in `byte`
+pub fn is_set(byte: u8, mask: u8) -> bool {
+    byte & mask == mask
+}
+
+/// Returns `byte` with the bits of `mask` set or cleared
+pub fn with_bit(byte: u8, mask: u8, set: bool) -> u8 {
+    if set {
+        byte | mask
+    } else {
+        byte & !mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_set() {
+        assert!(is_set(b'a', 0b00100000));
+        assert!(!is_set(b'A', 0b00100000));
+    }
+
+    #[test]
+    fn test_with_bit() {
+        assert_eq!(with_bit(b'A', 0b00100000, true), b'a');
+        assert_eq!(with_bit(b'a', 0b00100000, false), b'A');
+        assert_eq!(with_bit(b'a', 0b00100000, true), b'a');
+    }
 }
@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use pngme::png::Png;
+
+fn pngme(args: &[&str], stdin: &[u8]) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn corpus_png() -> Vec<u8> {
+    std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/corpus/libpng-pngtest.png"
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_encode_from_stdin_to_stdout() {
+    let input = corpus_png();
+    let output = pngme(
+        &[
+            "encode",
+            "-",
+            "--chunk-type",
+            "ruSt",
+            "--message",
+            "x",
+            "--stdout",
+        ],
+        &input,
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let png = Png::try_from(output.stdout.as_slice()).unwrap();
+    let chunk = png.chunk_by_type("ruSt").unwrap();
+    assert_eq!(chunk.data_as_string().unwrap(), "x");
+    assert_eq!(
+        png.chunks().len(),
+        Png::try_from(input.as_slice()).unwrap().chunks().len() + 1
+    );
+}
+
+#[test]
+fn test_encode_output_file_conflicts_with_stdout() {
+    let output = pngme(
+        &[
+            "encode",
+            "--file-path",
+            "-",
+            "out.png",
+            "--chunk-type",
+            "ruSt",
+            "--message",
+            "x",
+            "--stdout",
+        ],
+        &corpus_png(),
+    );
+    assert!(!output.status.success());
+}